@@ -0,0 +1,48 @@
+use std::fs;
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use bmfa::BitmapFontAtlas;
+use crate::AppError;
+
+
+/// Write a bitmap font atlas file whose bytes only depend on the atlas. The `bmfa` crate
+/// writes the glyph metadata straight out of a hash map, whose iteration order changes from
+/// run to run, and stamps each entry of the archive with the current time. So the file is
+/// written by `bmfa` first, and then rewritten in a canonical form.
+pub fn write_atlas_file(path: &Path, atlas: &BitmapFontAtlas) -> Result<(), AppError> {
+    if bmfa::write_to_file(path, atlas).is_err() {
+        return Err(AppError::CouldNotCreateAtlasFile(path.to_path_buf()));
+    }
+    match canonicalize_atlas_file(path) {
+        Ok(()) => Ok(()),
+        Err(_) => Err(AppError::CouldNotCreateAtlasFile(path.to_path_buf())),
+    }
+}
+
+/// Rewrite the archive of an atlas file with the same entries in the same order, each stamped
+/// with the earliest time a zip archive can hold. Entries holding JSON have the keys of every
+/// object sorted, and the other entries are copied as they are.
+fn canonicalize_atlas_file(path: &Path) -> zip::result::ZipResult<()> {
+    let contents = fs::read(path)?;
+    let mut archive = zip::ZipArchive::new(Cursor::new(contents))?;
+    let mut writer = zip::ZipWriter::new(File::create(path)?);
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let mut data = vec![];
+        entry.read_to_end(&mut data)?;
+        // Without the `preserve_order` feature, `serde_json` keeps the keys of an object in
+        // sorted order.
+        if let Ok(value) = serde_json::from_slice::<serde_json::Value>(&data) {
+            data = serde_json::to_vec(&value).map_err(std::io::Error::from)?;
+        }
+        let options = zip::write::FileOptions::default()
+            .compression_method(entry.compression())
+            .last_modified_time(zip::DateTime::default());
+        writer.start_file(entry.name(), options)?;
+        writer.write_all(&data)?;
+    }
+    writer.finish()?;
+
+    Ok(())
+}
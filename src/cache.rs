@@ -0,0 +1,235 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::{GlyphImage, GlyphSample};
+
+
+/// The version of `fontgen` that wrote the cache. Every cache key includes it, so that
+/// upgrading `fontgen` never reuses output from an older version.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The layout of a cached glyph file. Glyph cache keys include it, so that a change to the
+/// layout never reads back glyphs written in an older one.
+const GLYPH_FORMAT: usize = 4;
+
+/// The size of the header in front of the image data in a cached glyph file.
+const GLYPH_HEADER_SIZE: usize = 60;
+
+/// A 64 bit FNV-1a hasher. The standard library hasher is not guaranteed to be stable
+/// between releases, so cache keys use this instead.
+#[derive(Copy, Clone, Debug)]
+pub struct CacheKeyHasher {
+    state: u64,
+}
+
+impl CacheKeyHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub fn new() -> CacheKeyHasher {
+        let mut hasher = CacheKeyHasher {
+            state: Self::OFFSET_BASIS,
+        };
+        hasher.write_str(VERSION);
+
+        hasher
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter() {
+            self.state ^= *byte as u64;
+            self.state = self.state.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    /// Hash a string followed by a separator, so that consecutive strings cannot run together.
+    pub fn write_str(&mut self, st: &str) {
+        self.write(st.as_bytes());
+        self.write(&[0xff]);
+    }
+
+    pub fn write_usize(&mut self, value: usize) {
+        self.write(&(value as u64).to_le_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+/// Read a little endian `i32` from the front of a byte slice.
+fn read_i32(bytes: &[u8]) -> Option<i32> {
+    if bytes.len() < 4 {
+        return None;
+    }
+    let mut buffer = [0 as u8; 4];
+    buffer.copy_from_slice(&bytes[0..4]);
+
+    Some(i32::from_le_bytes(buffer))
+}
+
+/// Read a little endian `i64` from the front of a byte slice.
+fn read_i64(bytes: &[u8]) -> Option<i64> {
+    if bytes.len() < 8 {
+        return None;
+    }
+    let mut buffer = [0 as u8; 8];
+    buffer.copy_from_slice(&bytes[0..8]);
+
+    Some(i64::from_le_bytes(buffer))
+}
+
+/// A cache of the output of previous runs of `fontgen`, along with the individual glyph
+/// images those runs rendered.
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    /// Open the cache in a directory, creating the directory if it does not exist.
+    pub fn open(dir: &Path) -> io::Result<Cache> {
+        fs::create_dir_all(dir)?;
+
+        Ok(Cache {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// The directory holding the cache.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The directory holding the output files for a cache key.
+    fn output_dir(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("output-{:016x}", key))
+    }
+
+    /// Copy the output files for a cache key to their destinations. Each file is paired
+    /// with the name it is stored under in the cache. This returns `false` without copying
+    /// anything when the cache does not hold every file.
+    pub fn restore_output(&self, key: u64, files: &[(String, PathBuf)]) -> io::Result<bool> {
+        let output_dir = self.output_dir(key);
+        if !files.iter().all(|&(ref name, _)| output_dir.join(name).is_file()) {
+            return Ok(false);
+        }
+
+        for &(ref name, ref destination) in files.iter() {
+            fs::copy(output_dir.join(name), destination)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Store the output files for a cache key. The files are first copied into a staging
+    /// directory that is then moved into place, so an interrupted run never leaves a
+    /// partial entry behind.
+    pub fn store_output(&self, key: u64, files: &[(String, PathBuf)]) -> io::Result<()> {
+        let output_dir = self.output_dir(key);
+        let staging_dir = self.dir.join(format!("output-{:016x}.partial", key));
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir)?;
+        }
+        fs::create_dir_all(&staging_dir)?;
+        for &(ref name, ref source) in files.iter() {
+            fs::copy(source, staging_dir.join(name))?;
+        }
+        if output_dir.exists() {
+            fs::remove_dir_all(&output_dir)?;
+        }
+
+        fs::rename(&staging_dir, &output_dir)
+    }
+
+    /// The glyph cache for a single typeface rendered at a single glyph size, in the
+    /// rendering with the given name.
+    pub fn glyph_cache(&self, font_key: u64, glyph_size: usize, rendering: &str) -> io::Result<GlyphCache> {
+        let mut hasher = CacheKeyHasher::new();
+        hasher.write(&font_key.to_le_bytes());
+        hasher.write_usize(glyph_size);
+        hasher.write_str(rendering);
+        hasher.write_usize(GLYPH_FORMAT);
+        let dir = self.dir.join(format!("glyphs-{:016x}", hasher.finish()));
+        fs::create_dir_all(&dir)?;
+
+        Ok(GlyphCache {
+            dir: dir,
+        })
+    }
+}
+
+/// A cache of the glyphs rendered from one typeface at one glyph size, keyed by code point.
+#[derive(Clone)]
+pub struct GlyphCache {
+    dir: PathBuf,
+}
+
+impl GlyphCache {
+    fn glyph_path(&self, code_point: usize) -> PathBuf {
+        self.dir.join(format!("{}.glyph", code_point))
+    }
+
+    /// Load a previously rendered glyph. A glyph that is missing or that cannot be read
+    /// back is treated as not cached.
+    pub fn load(&self, code_point: usize) -> Option<GlyphSample> {
+        let bytes = fs::read(self.glyph_path(code_point)).ok()?;
+        if bytes.len() < GLYPH_HEADER_SIZE {
+            return None;
+        }
+        let rows = read_i32(&bytes[0..])?;
+        let width = read_i32(&bytes[4..])?;
+        let pitch = read_i32(&bytes[8..])?;
+        let y_min = read_i64(&bytes[12..])?;
+        let x_min = read_i64(&bytes[20..])?;
+        let advance = read_i64(&bytes[28..])?;
+        let vertical_origin_x = read_i64(&bytes[36..])?;
+        let vertical_origin_y = read_i64(&bytes[44..])?;
+        let vertical_advance = read_i64(&bytes[52..])?;
+        // The coverage of a color glyph is followed by its RGBA color.
+        let data_size = (rows.max(0) as usize) * (pitch.abs() as usize);
+        let image = match bytes.len() - GLYPH_HEADER_SIZE {
+            size if size == data_size => GlyphImage::new(bytes[GLYPH_HEADER_SIZE..].to_vec()),
+            size if size == 5 * data_size => {
+                let color_start = GLYPH_HEADER_SIZE + data_size;
+                GlyphImage::with_color(
+                    bytes[GLYPH_HEADER_SIZE..color_start].to_vec(), bytes[color_start..].to_vec()
+                )
+            }
+            _ => return None,
+        };
+
+        Some(GlyphSample {
+            code_point: code_point,
+            rows: rows,
+            width: width,
+            pitch: pitch,
+            y_min: y_min,
+            x_min: x_min,
+            advance: advance,
+            vertical_origin_x: vertical_origin_x,
+            vertical_origin_y: vertical_origin_y,
+            vertical_advance: vertical_advance,
+            image: image,
+        })
+    }
+
+    /// Store a rendered glyph.
+    pub fn store(&self, sample: &GlyphSample) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(GLYPH_HEADER_SIZE + sample.image.data.len());
+        bytes.extend_from_slice(&sample.rows.to_le_bytes());
+        bytes.extend_from_slice(&sample.width.to_le_bytes());
+        bytes.extend_from_slice(&sample.pitch.to_le_bytes());
+        bytes.extend_from_slice(&sample.y_min.to_le_bytes());
+        bytes.extend_from_slice(&sample.x_min.to_le_bytes());
+        bytes.extend_from_slice(&sample.advance.to_le_bytes());
+        bytes.extend_from_slice(&sample.vertical_origin_x.to_le_bytes());
+        bytes.extend_from_slice(&sample.vertical_origin_y.to_le_bytes());
+        bytes.extend_from_slice(&sample.vertical_advance.to_le_bytes());
+        bytes.extend_from_slice(&sample.image.data);
+        if let Some(ref color) = sample.image.color {
+            bytes.extend_from_slice(color);
+        }
+
+        fs::write(self.glyph_path(sample.code_point), bytes)
+    }
+}
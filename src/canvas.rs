@@ -0,0 +1,155 @@
+use std::path::Path;
+
+
+/// The width of a character in the label font, in pixels.
+const LABEL_GLYPH_WIDTH: usize = 3;
+/// The height of a character in the label font, in pixels.
+const LABEL_GLYPH_HEIGHT: usize = 5;
+/// A tiny bitmap font covering the hexadecimal digits, used for labelling diagnostic images.
+/// Each row of a character is stored in the low three bits of a byte, most significant bit first.
+const LABEL_GLYPHS: [[u8; LABEL_GLYPH_HEIGHT]; 16] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+    [0b010, 0b101, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b110, 0b101, 0b110],
+    [0b111, 0b100, 0b100, 0b100, 0b111],
+    [0b110, 0b101, 0b101, 0b101, 0b110],
+    [0b111, 0b100, 0b111, 0b100, 0b111],
+    [0b111, 0b100, 0b111, 0b100, 0b100],
+];
+
+/// An RGBA color with eight bits per channel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color {
+            r: r,
+            g: g,
+            b: b,
+            a: a,
+        }
+    }
+}
+
+/// A `Canvas` is an RGBA image in the top left coordinate chart that diagnostic images
+/// are drawn into before they get written out.
+pub struct Canvas {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Canvas {
+    /// Construct a canvas filled with a single background color.
+    pub fn new(width: usize, height: usize, background: Color) -> Canvas {
+        let mut data = Vec::with_capacity(4 * width * height);
+        for _ in 0..(width * height) {
+            data.push(background.r);
+            data.push(background.g);
+            data.push(background.b);
+            data.push(background.a);
+        }
+
+        Canvas {
+            width: width,
+            height: height,
+            data: data,
+        }
+    }
+
+    /// Blend a color over the pixel at `(x, y)`. Pixels outside of the canvas are ignored.
+    pub fn blend_pixel(&mut self, x: i64, y: i64, color: Color) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+
+        let index = 4 * (y as usize * self.width + x as usize);
+        let alpha = color.a as u32;
+        let inverse_alpha = 255 - alpha;
+        let src = [color.r as u32, color.g as u32, color.b as u32];
+        for channel in 0..3 {
+            let dst = self.data[index + channel] as u32;
+            self.data[index + channel] = ((src[channel] * alpha + dst * inverse_alpha) / 255) as u8;
+        }
+        let dst_alpha = self.data[index + 3] as u32;
+        self.data[index + 3] = (alpha + (dst_alpha * inverse_alpha) / 255) as u8;
+    }
+
+    /// Draw a horizontal line of length `length` starting at `(x, y)`.
+    pub fn draw_horizontal_line(&mut self, x: i64, y: i64, length: usize, color: Color) {
+        for i in 0..(length as i64) {
+            self.blend_pixel(x + i, y, color);
+        }
+    }
+
+    /// Draw a vertical line of length `length` starting at `(x, y)`.
+    pub fn draw_vertical_line(&mut self, x: i64, y: i64, length: usize, color: Color) {
+        for i in 0..(length as i64) {
+            self.blend_pixel(x, y + i, color);
+        }
+    }
+
+    /// Draw the outline of a rectangle with its top left corner at `(x, y)`.
+    pub fn draw_rectangle(&mut self, x: i64, y: i64, width: usize, height: usize, color: Color) {
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        self.draw_horizontal_line(x, y, width, color);
+        self.draw_horizontal_line(x, y + height as i64 - 1, width, color);
+        self.draw_vertical_line(x, y, height, color);
+        self.draw_vertical_line(x + width as i64 - 1, y, height, color);
+    }
+
+    /// Fill a rectangle with its top left corner at `(x, y)`.
+    pub fn fill_rectangle(&mut self, x: i64, y: i64, width: usize, height: usize, color: Color) {
+        for j in 0..(height as i64) {
+            self.draw_horizontal_line(x, y + j, width, color);
+        }
+    }
+
+    /// Draw a label made of hexadecimal digits with its top left corner at `(x, y)`. The label
+    /// is drawn over a dark backdrop so it stays readable on top of the glyph images.
+    /// Characters that are not hexadecimal digits are left blank.
+    pub fn draw_label(&mut self, x: i64, y: i64, label: &str, color: Color) {
+        let advance = (LABEL_GLYPH_WIDTH + 1) as i64;
+        let width = label.chars().count() * (LABEL_GLYPH_WIDTH + 1) + 1;
+        self.fill_rectangle(x, y, width, LABEL_GLYPH_HEIGHT + 2, Color::new(0, 0, 0, 192));
+        for (i, ch) in label.chars().enumerate() {
+            let digit = match ch.to_digit(16) {
+                Some(digit) => digit as usize,
+                None => continue,
+            };
+            let glyph_x = x + 1 + i as i64 * advance;
+            for (row, bits) in LABEL_GLYPHS[digit].iter().enumerate() {
+                for col in 0..LABEL_GLYPH_WIDTH {
+                    if bits & (1 << (LABEL_GLYPH_WIDTH - col - 1)) != 0 {
+                        self.blend_pixel(glyph_x + col as i64, y + 1 + row as i64, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Write the canvas out as a PNG image.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        image::save_buffer(
+            path, &self.data, self.width as u32, self.height as u32, image::ColorType::RGBA(8)
+        )
+    }
+}
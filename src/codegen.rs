@@ -0,0 +1,307 @@
+use std::fmt::Write;
+use crate::export::AtlasExport;
+use crate::mono;
+
+
+/// The number of pixel values written on each line of a generated byte array.
+const BYTES_PER_LINE: usize = 16;
+
+/// The coverage of each pixel of the atlas image, one byte per pixel, with the rows running
+/// from the top of the image down whatever the origin of the atlas is.
+pub fn coverage_pixels(image: &bmfa::BitmapFontAtlasImage) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(image.width * image.height);
+    for y in 0..image.height {
+        let row = match image.origin {
+            bmfa::Origin::BottomLeft => image.height - y - 1,
+            bmfa::Origin::TopLeft => y,
+        };
+        for x in 0..image.width {
+            // Every channel holds the coverage, so any of them will do.
+            pixels.push(image.data[4 * (row * image.width + x) + 3]);
+        }
+    }
+
+    pixels
+}
+
+/// Pack the bitmap of every glyph of a monochrome atlas, or nothing for any other atlas.
+fn packed_bitmaps(export: &AtlasExport) -> Option<mono::PackedBitmaps> {
+    let packing = export.mono?;
+
+    Some(mono::pack_atlas(packing, export.image, &export.metrics.glyphs))
+}
+
+/// Write out the elements of a byte array, indented, `BYTES_PER_LINE` bytes to a line.
+fn write_byte_array(source: &mut String, bytes: &[u8]) {
+    for line in bytes.chunks(BYTES_PER_LINE) {
+        source.push_str("    ");
+        for (i, byte) in line.iter().enumerate() {
+            if i > 0 {
+                source.push(' ');
+            }
+            let _ = write!(source, "0x{:02X},", byte);
+        }
+        source.push('\n');
+    }
+}
+
+/// Generate a Rust module embedding the atlas. The module holds the coverage of every pixel
+/// in a `static` array, a table of glyph metrics sorted by code point, and a function looking
+/// up the glyph for a character. A monochrome atlas gets a packed bitmap for each glyph in
+/// place of the pixels. It only uses `core`, and allocates nothing, so it can be compiled
+/// into `no_std` firmware.
+pub fn rust_module(export: &AtlasExport) -> String {
+    let font = export.font;
+    let bitmaps = packed_bitmaps(export);
+    let mut source = String::new();
+    let _ = writeln!(
+        source, "//! The {} {} typeface at {} pixels, generated by fontgen {}. Do not edit.",
+        font.family, font.style, font.size, env!("CARGO_PKG_VERSION")
+    );
+    source.push_str("\n\n");
+    source.push_str("/// The placement and metrics of a single glyph in the atlas, in pixels.\n");
+    source.push_str("#[derive(Copy, Clone, Debug, PartialEq, Eq)]\n");
+    source.push_str("pub struct Glyph {\n");
+    source.push_str("    /// The character the glyph draws.\n");
+    source.push_str("    pub code_point: char,\n");
+    if bitmaps.is_some() {
+        source.push_str("    /// The offset of the packed bitmap of the glyph in `BITMAPS`.\n");
+        source.push_str("    pub bitmap: u32,\n");
+    } else {
+        source.push_str("    /// The left edge of the glyph rectangle in the atlas.\n");
+        source.push_str("    pub x: u32,\n");
+        source.push_str("    /// The top edge of the glyph rectangle, measured from the top of the atlas.\n");
+        source.push_str("    pub y: u32,\n");
+    }
+    source.push_str("    /// The width of the glyph rectangle.\n");
+    source.push_str("    pub width: u32,\n");
+    source.push_str("    /// The height of the glyph rectangle.\n");
+    source.push_str("    pub height: u32,\n");
+    source.push_str("    /// The offset from the pen position to the left edge of the glyph rectangle.\n");
+    source.push_str("    pub x_offset: i32,\n");
+    source.push_str("    /// The offset from the baseline up to the top edge of the glyph rectangle.\n");
+    source.push_str("    pub y_offset: i32,\n");
+    source.push_str("    /// The distance the pen advances after the glyph.\n");
+    source.push_str("    pub advance: i32,\n");
+    source.push_str("    /// The offset from the pen position in vertical text to the left edge of the glyph rectangle.\n");
+    source.push_str("    pub vertical_x_offset: i32,\n");
+    source.push_str("    /// The offset from the pen position in vertical text up to the top edge of the glyph rectangle.\n");
+    source.push_str("    pub vertical_y_offset: i32,\n");
+    source.push_str("    /// The distance the pen advances down after the glyph in vertical text.\n");
+    source.push_str("    pub vertical_advance: i32,\n");
+    source.push_str("}\n\n");
+
+    if bitmaps.is_none() {
+        source.push_str("/// The width of the atlas in pixels.\n");
+        let _ = writeln!(source, "pub const ATLAS_WIDTH: usize = {};", export.image.width);
+        source.push_str("/// The height of the atlas in pixels.\n");
+        let _ = writeln!(source, "pub const ATLAS_HEIGHT: usize = {};", export.image.height);
+    }
+    source.push_str("/// The distance in pixels from the baseline up to the top of the tallest glyphs.\n");
+    let _ = writeln!(source, "pub const ASCENDER: f32 = {:?};", font.ascender);
+    source.push_str("/// The distance in pixels from the baseline down to the bottom of the lowest glyphs.\n");
+    let _ = writeln!(source, "pub const DESCENDER: f32 = {:?};", font.descender);
+    source.push_str("/// The distance in pixels between the baselines of two consecutive lines of text.\n");
+    let _ = writeln!(source, "pub const LINE_HEIGHT: f32 = {:?};", font.line_height);
+    source.push('\n');
+
+    match bitmaps {
+        Some(ref bitmaps) => {
+            let packing = export.mono.expect("A monochrome atlas has a packing.");
+            let _ = writeln!(source, "/// The packed bitmaps of the glyphs. {}", packing.description());
+            let _ = writeln!(source, "pub static BITMAPS: [u8; {}] = [", bitmaps.data.len());
+            write_byte_array(&mut source, &bitmaps.data);
+        }
+        None => {
+            let pixels = coverage_pixels(export.image);
+            source.push_str("/// The coverage of each pixel of the atlas, one byte per pixel, row by row from the top.\n");
+            let _ = writeln!(source, "pub static PIXELS: [u8; {}] = [", pixels.len());
+            write_byte_array(&mut source, &pixels);
+        }
+    }
+    source.push_str("];\n\n");
+
+    source.push_str("/// The glyphs in the atlas, sorted by code point.\n");
+    source.push_str("pub static GLYPHS: &[Glyph] = &[\n");
+    for (&code_point, glyph) in export.metrics.glyphs.iter() {
+        let placement = match bitmaps {
+            Some(ref bitmaps) => format!("bitmap: {}", bitmaps.offsets[&code_point]),
+            None => format!("x: {}, y: {}", glyph.x, glyph.y),
+        };
+        let _ = writeln!(
+            source,
+            "    Glyph {{ code_point: '\\u{{{:X}}}', {}, width: {}, height: {}, \
+            x_offset: {}, y_offset: {}, advance: {}, \
+            vertical_x_offset: {}, vertical_y_offset: {}, vertical_advance: {} }},",
+            code_point, placement, glyph.width, glyph.height,
+            glyph.x_offset, glyph.y_offset, glyph.advance,
+            glyph.vertical_x_offset, glyph.vertical_y_offset, glyph.vertical_advance
+        );
+    }
+    source.push_str("];\n\n");
+
+    source.push_str("/// Look up the glyph for a character, if the atlas has one.\n");
+    source.push_str("pub fn glyph(code_point: char) -> Option<&'static Glyph> {\n");
+    source.push_str("    match GLYPHS.binary_search_by_key(&code_point, |glyph| glyph.code_point) {\n");
+    source.push_str("        Ok(index) => Some(&GLYPHS[index]),\n");
+    source.push_str("        Err(_) => None,\n");
+    source.push_str("    }\n");
+    source.push_str("}\n");
+
+    source
+}
+
+/// Turn a name into a C identifier, in lower case, by replacing every character that cannot
+/// appear in one with an underscore.
+pub fn c_identifier(name: &str) -> String {
+    let mut identifier: String = name.chars().map(|c| {
+        if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }
+    }).collect();
+    if identifier.is_empty() || identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        identifier.insert(0, '_');
+    }
+
+    identifier
+}
+
+/// Whether a symbol prefix is a valid C identifier.
+pub fn is_c_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Generate a single C header embedding the atlas, with every symbol starting with the
+/// symbol prefix of the export. The header holds the coverage of every pixel as a
+/// `const uint8_t` array, or a packed bitmap for each glyph of a monochrome atlas, and arrays
+/// of the glyph metrics sorted by code point and of the kerning pairs, next to the metrics
+/// of the font.
+pub fn c_header(export: &AtlasExport) -> String {
+    let font = export.font;
+    let prefix = &export.symbol_prefix;
+    let upper = prefix.to_ascii_uppercase();
+    let bitmaps = packed_bitmaps(export);
+    let mut source = String::new();
+    let _ = writeln!(
+        source, "/* The {} {} typeface at {} pixels, generated by fontgen {}. Do not edit. */",
+        font.family, font.style, font.size, env!("CARGO_PKG_VERSION")
+    );
+    let _ = writeln!(source, "#ifndef {}_H", upper);
+    let _ = writeln!(source, "#define {}_H", upper);
+    source.push_str("\n#include <stdint.h>\n\n");
+
+    if bitmaps.is_none() {
+        source.push_str("/* The dimensions of the atlas in pixels, and the number of glyphs and kerning pairs. */\n");
+        let _ = writeln!(source, "#define {}_ATLAS_WIDTH {}", upper, export.image.width);
+        let _ = writeln!(source, "#define {}_ATLAS_HEIGHT {}", upper, export.image.height);
+    } else {
+        source.push_str("/* The number of glyphs and kerning pairs. */\n");
+    }
+    let _ = writeln!(source, "#define {}_GLYPH_COUNT {}", upper, export.metrics.glyphs.len());
+    let _ = writeln!(source, "#define {}_KERNING_PAIR_COUNT {}", upper, export.kerning.len());
+    source.push('\n');
+
+    if bitmaps.is_some() {
+        source.push_str("/* The bitmap and metrics of a single glyph, in pixels. The bitmap starts at an offset\n");
+        let _ = writeln!(source, " * into {}_bitmaps, and the offsets are measured from the pen position on the", prefix);
+        source.push_str(" * baseline, or from the pen position above the glyph in vertical text, with the y axis\n");
+        source.push_str(" * pointing up. */\n");
+        let _ = writeln!(source, "typedef struct {}_glyph {{", prefix);
+        source.push_str("    uint32_t code_point;\n");
+        source.push_str("    uint32_t bitmap;\n");
+    } else {
+        source.push_str("/* The placement and metrics of a single glyph in the atlas, in pixels. The top edge\n");
+        source.push_str(" * of the glyph rectangle is measured from the top of the atlas, and the offsets are\n");
+        source.push_str(" * measured from the pen position on the baseline, or from the pen position above the\n");
+        source.push_str(" * glyph in vertical text, with the y axis pointing up. */\n");
+        let _ = writeln!(source, "typedef struct {}_glyph {{", prefix);
+        source.push_str("    uint32_t code_point;\n");
+        source.push_str("    uint32_t x;\n");
+        source.push_str("    uint32_t y;\n");
+    }
+    source.push_str("    uint32_t width;\n");
+    source.push_str("    uint32_t height;\n");
+    source.push_str("    int32_t x_offset;\n");
+    source.push_str("    int32_t y_offset;\n");
+    source.push_str("    int32_t advance;\n");
+    source.push_str("    int32_t vertical_x_offset;\n");
+    source.push_str("    int32_t vertical_y_offset;\n");
+    source.push_str("    int32_t vertical_advance;\n");
+    let _ = writeln!(source, "}} {}_glyph;\n", prefix);
+
+    source.push_str("/* The adjustment in pixels to the advance of the left glyph when the right glyph\n");
+    source.push_str(" * follows it. */\n");
+    let _ = writeln!(source, "typedef struct {}_kerning_pair {{", prefix);
+    source.push_str("    uint32_t left;\n");
+    source.push_str("    uint32_t right;\n");
+    source.push_str("    float advance;\n");
+    let _ = writeln!(source, "}} {}_kerning_pair;\n", prefix);
+
+    source.push_str("/* The metrics of the font in pixels, measured up from the baseline. */\n");
+    let _ = writeln!(source, "typedef struct {}_font_metrics {{", prefix);
+    source.push_str("    uint32_t size;\n");
+    source.push_str("    float ascender;\n");
+    source.push_str("    float descender;\n");
+    source.push_str("    float line_height;\n");
+    source.push_str("    float underline_position;\n");
+    source.push_str("    float underline_thickness;\n");
+    let _ = writeln!(source, "}} {}_font_metrics;\n", prefix);
+
+    let _ = writeln!(source, "static const {}_font_metrics {}_metrics = {{", prefix, prefix);
+    let _ = writeln!(
+        source, "    {}, {:?}f, {:?}f, {:?}f, {:?}f, {:?}f",
+        font.size, font.ascender, font.descender, font.line_height,
+        font.underline_position, font.underline_thickness
+    );
+    source.push_str("};\n\n");
+
+    match bitmaps {
+        Some(ref bitmaps) => {
+            let packing = export.mono.expect("A monochrome atlas has a packing.");
+            let _ = writeln!(source, "/* The packed bitmaps of the glyphs. {} */", packing.description());
+            let _ = writeln!(source, "static const uint8_t {}_bitmaps[{}] = {{", prefix, bitmaps.data.len());
+            write_byte_array(&mut source, &bitmaps.data);
+        }
+        None => {
+            let pixels = coverage_pixels(export.image);
+            source.push_str("/* The coverage of each pixel of the atlas, one byte per pixel, row by row from the top. */\n");
+            let _ = writeln!(source, "static const uint8_t {}_pixels[{}] = {{", prefix, pixels.len());
+            write_byte_array(&mut source, &pixels);
+        }
+    }
+    source.push_str("};\n\n");
+
+    source.push_str("/* The glyphs in the atlas, sorted by code point. */\n");
+    let _ = writeln!(source, "static const {}_glyph {}_glyphs[{}_GLYPH_COUNT] = {{", prefix, prefix, upper);
+    for (&code_point, glyph) in export.metrics.glyphs.iter() {
+        let placement = match bitmaps {
+            Some(ref bitmaps) => format!("{}", bitmaps.offsets[&code_point]),
+            None => format!("{}, {}", glyph.x, glyph.y),
+        };
+        let _ = writeln!(
+            source, "    {{ 0x{:X}, {}, {}, {}, {}, {}, {}, {}, {}, {} }},",
+            code_point, placement, glyph.width, glyph.height,
+            glyph.x_offset, glyph.y_offset, glyph.advance,
+            glyph.vertical_x_offset, glyph.vertical_y_offset, glyph.vertical_advance
+        );
+    }
+    source.push_str("};\n\n");
+
+    // C has no empty arrays, so a font without kerning gets a single unused pair.
+    source.push_str("/* The kerning pairs of the font, sorted by the left code point, then the right one. */\n");
+    let _ = writeln!(
+        source, "static const {}_kerning_pair {}_kerning[{}_KERNING_PAIR_COUNT > 0 ? {}_KERNING_PAIR_COUNT : 1] = {{",
+        prefix, prefix, upper, upper
+    );
+    for pair in export.kerning.iter() {
+        let _ = writeln!(source, "    {{ 0x{:X}, 0x{:X}, {:?}f }},", pair.left, pair.right, pair.advance);
+    }
+    if export.kerning.is_empty() {
+        source.push_str("    { 0, 0, 0.0f },\n");
+    }
+    source.push_str("};\n\n");
+
+    let _ = writeln!(source, "#endif /* {}_H */", upper);
+
+    source
+}
@@ -0,0 +1,504 @@
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crate::mono::MonoPacking;
+use crate::{codegen, AppError, AtlasMetrics, OptError, OrderedAtlasMetadata};
+
+
+/// The version of the layout of the JSON metadata written by `--format json`. This goes up
+/// whenever a field is removed or changes meaning, and the schema in
+/// `schemas/fontgen-atlas-v1.schema.json` describes each version.
+pub const JSON_SCHEMA_VERSION: usize = 1;
+
+/// A format the atlas can be exported to, next to the atlas file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// The engine agnostic JSON metadata described by the fontgen atlas JSON Schema.
+    Json,
+    /// The JSON layout written by msdf-atlas-gen, as read by its shaders and by text
+    /// renderers built around it.
+    MsdfAtlasJson,
+    /// A Rust module embedding the atlas pixels and glyph metrics, for compiling the atlas
+    /// into a program.
+    Rust,
+    /// A C header embedding the atlas pixels, glyph metrics, and kerning pairs.
+    CHeader,
+}
+
+impl ExportFormat {
+    /// The name of the format as it is written on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::MsdfAtlasJson => "msdf-atlas-json",
+            ExportFormat::Rust => "rust",
+            ExportFormat::CHeader => "c-header",
+        }
+    }
+
+    /// The extension of the file the format gets written to, in place of the extension
+    /// of the atlas file.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "atlas.json",
+            ExportFormat::MsdfAtlasJson => "msdf.json",
+            ExportFormat::Rust => "rs",
+            ExportFormat::CHeader => "h",
+        }
+    }
+}
+
+pub fn parse_export_format(st: &str) -> Result<ExportFormat, OptError> {
+    match st {
+        "json" => Ok(ExportFormat::Json),
+        "msdf-atlas-json" => Ok(ExportFormat::MsdfAtlasJson),
+        "rust" => Ok(ExportFormat::Rust),
+        "c-header" => Ok(ExportFormat::CHeader),
+        _ => Err(OptError::InvalidExportFormat(format!("{}", st))),
+    }
+}
+
+/// The path to the file an export format gets written to next to an atlas file.
+pub fn export_path(atlas_path: &Path, format: ExportFormat) -> PathBuf {
+    atlas_path.with_extension(format.extension())
+}
+
+/// The typeface the atlas was generated from. The metrics are in pixels at the size the
+/// glyphs were rendered at, with the y axis pointing up from the baseline.
+#[derive(Clone, Debug, Serialize)]
+pub struct FontInfo {
+    /// The family name of the typeface, such as `FreeMono`.
+    pub family: String,
+    /// The style name of the typeface, such as `Regular` or `Bold`.
+    pub style: String,
+    /// The number of font units per em square in the font file.
+    pub units_per_em: i64,
+    /// The size of the em square in pixels the glyphs were rendered at.
+    pub size: usize,
+    /// The distance from the baseline up to the top of the tallest glyphs.
+    pub ascender: f32,
+    /// The distance from the baseline to the bottom of the lowest glyphs. This is negative
+    /// for glyphs dipping below the baseline.
+    pub descender: f32,
+    /// The distance between the baselines of two consecutive lines of text.
+    pub line_height: f32,
+    /// The position of the underline relative to the baseline.
+    pub underline_position: f32,
+    /// The thickness of the underline.
+    pub underline_thickness: f32,
+}
+
+/// Read the family, the style, and the global metrics of a typeface, scaled from font units
+/// to the pixels of an em square `size` pixels tall. Bitmap fonts have no font units, so their
+/// metrics are read from the strike the face is set to instead, scaled from the size of the
+/// strike.
+pub fn font_info(face: &freetype::face::Face, size: usize) -> FontInfo {
+    let units_per_em = face.em_size() as i64;
+    if units_per_em == 0 {
+        // The size metrics are in 26.6 fixed point.
+        let size_metrics = face.size_metrics();
+        let pixels = |metric: fn(&freetype::ffi::FT_Size_Metrics) -> i64| -> f32 {
+            size_metrics.as_ref().filter(|metrics| metrics.y_ppem > 0).map_or(0.0, |metrics| {
+                metric(metrics) as f32 / 64.0 * size as f32 / metrics.y_ppem as f32
+            })
+        };
+
+        return FontInfo {
+            family: face.family_name().unwrap_or_default(),
+            style: face.style_name().unwrap_or_default(),
+            units_per_em: 0,
+            size: size,
+            ascender: pixels(|metrics| metrics.ascender as i64),
+            descender: pixels(|metrics| metrics.descender as i64),
+            line_height: pixels(|metrics| metrics.height as i64),
+            underline_position: 0.0,
+            underline_thickness: 0.0,
+        };
+    }
+    let scale = |units: i16| -> f32 {
+        if units_per_em > 0 {
+            units as f32 * size as f32 / units_per_em as f32
+        } else {
+            0.0
+        }
+    };
+
+    FontInfo {
+        family: face.family_name().unwrap_or_default(),
+        style: face.style_name().unwrap_or_default(),
+        units_per_em: units_per_em,
+        size: size,
+        ascender: scale(face.ascender()),
+        descender: scale(face.descender()),
+        line_height: scale(face.height()),
+        underline_position: scale(face.underline_position()),
+        underline_thickness: scale(face.underline_thickness()),
+    }
+}
+
+/// The extra distance the pen moves between two glyphs when one follows the other.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct KerningPair {
+    /// The key of the first glyph.
+    pub left: usize,
+    /// The key of the glyph following it.
+    pub right: usize,
+    /// The adjustment to the advance of the first glyph in pixels. Negative values move
+    /// the glyphs closer together.
+    pub advance: f32,
+}
+
+/// Look up the kerning between every pair of glyphs in the font's kerning table, scaled
+/// to the pixels of an em square `size` pixels tall. The glyphs are given by their keys in
+/// the atlas: a code point, or a glyph index for glyphs sampled by glyph index. Pairs without
+/// any kerning are left out.
+pub fn kerning_pairs(face: &freetype::face::Face, keys: &[usize], size: usize) -> Vec<KerningPair> {
+    let units_per_em = face.em_size() as f32;
+    if !face.has_kerning() || !(units_per_em > 0.0) {
+        return vec![];
+    }
+
+    let glyph_indices: Vec<(usize, u32)> = keys.iter()
+        .map(|&key| if crate::is_glyph_index_key(key) {
+            (key, (key - crate::GLYPH_INDEX_KEY_BASE) as u32)
+        } else {
+            (key, face.get_char_index(key))
+        })
+        .filter(|&(_, glyph_index)| glyph_index != 0)
+        .collect();
+    let mut pairs = vec![];
+    for &(left, left_index) in glyph_indices.iter() {
+        for &(right, right_index) in glyph_indices.iter() {
+            let kerning = match face.get_kerning(
+                left_index, right_index, freetype::face::KerningMode::KerningUnscaled) {
+
+                Ok(val) => val,
+                Err(_) => continue,
+            };
+            if kerning.x != 0 {
+                pairs.push(KerningPair {
+                    left: left,
+                    right: right,
+                    advance: kerning.x as f32 * size as f32 / units_per_em,
+                });
+            }
+        }
+    }
+
+    pairs
+}
+
+/// Everything an exporter gets to know about a generated atlas.
+pub struct AtlasExport<'a> {
+    /// The file name of the atlas file, relative to the exported files.
+    pub atlas_file: String,
+    pub font: &'a FontInfo,
+    pub metadata: &'a OrderedAtlasMetadata,
+    pub metrics: &'a AtlasMetrics,
+    pub kerning: &'a [KerningPair],
+    pub image: &'a bmfa::BitmapFontAtlasImage,
+    /// The prefix of every symbol in generated source code.
+    pub symbol_prefix: String,
+    /// The packing of the glyph bitmaps of a monochrome atlas. Generated source code holds
+    /// the packed bitmaps instead of the atlas image.
+    pub mono: Option<MonoPacking>,
+}
+
+/// A rectangle of pixels in the atlas image, measured from the top left corner of the image
+/// whatever the origin of the atlas is.
+#[derive(Copy, Clone, Debug, Serialize)]
+struct JsonPixelRect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+/// The edges of a rectangle. Texture coordinates run from zero to one across the atlas image,
+/// with `v` measured from the origin of the atlas. Plane bounds are in pixels from the pen
+/// position on the baseline, with the y axis pointing up.
+#[derive(Copy, Clone, Debug, Serialize)]
+struct JsonBounds {
+    left: f32,
+    bottom: f32,
+    right: f32,
+    top: f32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct JsonGlyph {
+    code_point: usize,
+    /// The distance in pixels the pen advances after the glyph.
+    advance: f32,
+    /// Glyphs without an image, like the space, have no rectangles.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pixel_rect: Option<JsonPixelRect>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uv_rect: Option<JsonBounds>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plane_bounds: Option<JsonBounds>,
+    /// The distance in pixels the pen advances down after the glyph in vertical text.
+    vertical_advance: f32,
+    /// The plane bounds from the pen position in vertical text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vertical_plane_bounds: Option<JsonBounds>,
+    /// Only color glyphs are marked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<bool>,
+    glyph_id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mapped_code_point: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct JsonAtlasInfo {
+    file: String,
+    width: usize,
+    height: usize,
+    pixel_format: &'static str,
+    origin: &'static str,
+    padding: usize,
+    extrude: usize,
+    spacing: usize,
+}
+
+/// The engine agnostic JSON metadata of an atlas.
+#[derive(Clone, Debug, Serialize)]
+struct JsonAtlas {
+    version: usize,
+    generator: String,
+    font: FontInfo,
+    atlas: JsonAtlasInfo,
+    glyphs: BTreeMap<usize, JsonGlyph>,
+    kerning: Vec<KerningPair>,
+}
+
+fn create_json_atlas(export: &AtlasExport) -> JsonAtlas {
+    let metadata = export.metadata;
+    let width = metadata.width as f32;
+    let height = metadata.height as f32;
+    let glyphs = export.metrics.glyphs.iter().map(|(&code_point, glyph)| {
+        let has_image = glyph.width > 0 && glyph.height > 0;
+        let pixel_rect = JsonPixelRect {
+            x: glyph.x,
+            y: glyph.y,
+            width: glyph.width,
+            height: glyph.height,
+        };
+        let (v_top, v_bottom) = match metadata.origin {
+            bmfa::Origin::BottomLeft => {
+                (1.0 - glyph.y as f32 / height, 1.0 - (glyph.y + glyph.height) as f32 / height)
+            }
+            bmfa::Origin::TopLeft => {
+                (glyph.y as f32 / height, (glyph.y + glyph.height) as f32 / height)
+            }
+        };
+        let uv_rect = JsonBounds {
+            left: glyph.x as f32 / width,
+            bottom: v_bottom,
+            right: (glyph.x + glyph.width) as f32 / width,
+            top: v_top,
+        };
+        let plane_bounds = JsonBounds {
+            left: glyph.x_offset as f32,
+            bottom: (glyph.y_offset - glyph.height as i64) as f32,
+            right: (glyph.x_offset + glyph.width as i64) as f32,
+            top: glyph.y_offset as f32,
+        };
+        let vertical_plane_bounds = JsonBounds {
+            left: glyph.vertical_x_offset as f32,
+            bottom: (glyph.vertical_y_offset - glyph.height as i64) as f32,
+            right: (glyph.vertical_x_offset + glyph.width as i64) as f32,
+            top: glyph.vertical_y_offset as f32,
+        };
+        let json_glyph = JsonGlyph {
+            code_point: code_point,
+            advance: glyph.advance as f32,
+            pixel_rect: Some(pixel_rect).filter(|_| has_image),
+            uv_rect: Some(uv_rect).filter(|_| has_image),
+            plane_bounds: Some(plane_bounds).filter(|_| has_image),
+            vertical_advance: glyph.vertical_advance as f32,
+            vertical_plane_bounds: Some(vertical_plane_bounds).filter(|_| has_image),
+            color: Some(true).filter(|_| glyph.color),
+            glyph_id: glyph.glyph_id,
+            mapped_code_point: glyph.mapped_code_point,
+        };
+
+        (code_point, json_glyph)
+    }).collect();
+
+    JsonAtlas {
+        version: JSON_SCHEMA_VERSION,
+        generator: format!("fontgen {}", env!("CARGO_PKG_VERSION")),
+        font: export.font.clone(),
+        atlas: JsonAtlasInfo {
+            file: export.atlas_file.clone(),
+            width: metadata.width,
+            height: metadata.height,
+            pixel_format: "rgba8",
+            origin: crate::origin_name(metadata.origin),
+            padding: metadata.padding,
+            extrude: export.metrics.extrude,
+            spacing: export.metrics.spacing,
+        },
+        glyphs: glyphs,
+        kerning: export.kerning.to_vec(),
+    }
+}
+
+/// The atlas description in the msdf-atlas-gen layout. Like the atlases written by
+/// msdf-atlas-gen with a `softmask` type, the glyphs hold anti-aliased coverage, so there
+/// is no distance range.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MsdfAtlasInfo {
+    #[serde(rename = "type")]
+    atlas_type: &'static str,
+    size: usize,
+    width: usize,
+    height: usize,
+    y_origin: &'static str,
+}
+
+/// The font metrics in the msdf-atlas-gen layout, in ems.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MsdfMetrics {
+    em_size: usize,
+    line_height: f32,
+    ascender: f32,
+    descender: f32,
+    underline_y: f32,
+    underline_thickness: f32,
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+struct MsdfBounds {
+    left: f32,
+    bottom: f32,
+    right: f32,
+    top: f32,
+}
+
+/// A glyph in the msdf-atlas-gen layout. The plane bounds are in ems from the pen position
+/// on the baseline, and the atlas bounds are in pixels from the origin of the atlas.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MsdfGlyph {
+    unicode: usize,
+    advance: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plane_bounds: Option<MsdfBounds>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    atlas_bounds: Option<MsdfBounds>,
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+struct MsdfKerningPair {
+    unicode1: usize,
+    unicode2: usize,
+    advance: f32,
+}
+
+/// The atlas metadata in the layout of the JSON files written by msdf-atlas-gen.
+#[derive(Clone, Debug, Serialize)]
+struct MsdfAtlas {
+    atlas: MsdfAtlasInfo,
+    metrics: MsdfMetrics,
+    glyphs: Vec<MsdfGlyph>,
+    kerning: Vec<MsdfKerningPair>,
+}
+
+/// Map an atlas onto the msdf-atlas-gen layout. Lengths outside of the atlas are in ems.
+/// When the origin of the atlas is the top left corner, msdf-atlas-gen points the y axis
+/// down, so every vertical position is flipped to match.
+fn create_msdf_atlas(export: &AtlasExport) -> MsdfAtlas {
+    let metadata = export.metadata;
+    let font = export.font;
+    let em = font.size as f32;
+    let y_sign = match metadata.origin {
+        bmfa::Origin::BottomLeft => 1.0,
+        bmfa::Origin::TopLeft => -1.0,
+    };
+    let glyphs = export.metrics.glyphs.iter().map(|(&code_point, glyph)| {
+        let has_image = glyph.width > 0 && glyph.height > 0;
+        let plane_bounds = MsdfBounds {
+            left: glyph.x_offset as f32 / em,
+            bottom: y_sign * (glyph.y_offset - glyph.height as i64) as f32 / em,
+            right: (glyph.x_offset + glyph.width as i64) as f32 / em,
+            top: y_sign * glyph.y_offset as f32 / em,
+        };
+        let atlas_bounds = match metadata.origin {
+            bmfa::Origin::BottomLeft => MsdfBounds {
+                left: glyph.x as f32,
+                bottom: (metadata.height - glyph.y - glyph.height) as f32,
+                right: (glyph.x + glyph.width) as f32,
+                top: (metadata.height - glyph.y) as f32,
+            },
+            bmfa::Origin::TopLeft => MsdfBounds {
+                left: glyph.x as f32,
+                bottom: (glyph.y + glyph.height) as f32,
+                right: (glyph.x + glyph.width) as f32,
+                top: glyph.y as f32,
+            },
+        };
+
+        MsdfGlyph {
+            unicode: code_point,
+            advance: glyph.advance as f32 / em,
+            plane_bounds: Some(plane_bounds).filter(|_| has_image),
+            atlas_bounds: Some(atlas_bounds).filter(|_| has_image),
+        }
+    }).collect();
+    let kerning = export.kerning.iter().map(|pair| {
+        MsdfKerningPair {
+            unicode1: pair.left,
+            unicode2: pair.right,
+            advance: pair.advance / em,
+        }
+    }).collect();
+
+    MsdfAtlas {
+        atlas: MsdfAtlasInfo {
+            atlas_type: "softmask",
+            size: font.size,
+            width: metadata.width,
+            height: metadata.height,
+            y_origin: match metadata.origin {
+                bmfa::Origin::BottomLeft => "bottom",
+                bmfa::Origin::TopLeft => "top",
+            },
+        },
+        metrics: MsdfMetrics {
+            em_size: 1,
+            line_height: font.line_height / em,
+            ascender: y_sign * font.ascender / em,
+            descender: y_sign * font.descender / em,
+            underline_y: y_sign * font.underline_position / em,
+            underline_thickness: font.underline_thickness / em,
+        },
+        glyphs: glyphs,
+        kerning: kerning,
+    }
+}
+
+/// Write an atlas out in an export format.
+pub fn write_export(path: &Path, format: ExportFormat, export: &AtlasExport) -> Result<(), AppError> {
+    let mut file = match File::create(path) {
+        Ok(val) => val,
+        Err(_) => return Err(AppError::CouldNotCreateAtlasFile(path.to_path_buf())),
+    };
+    let written = match format {
+        ExportFormat::Json => serde_json::to_writer_pretty(file, &create_json_atlas(export)).is_ok(),
+        ExportFormat::MsdfAtlasJson => serde_json::to_writer_pretty(file, &create_msdf_atlas(export)).is_ok(),
+        ExportFormat::Rust => file.write_all(codegen::rust_module(export).as_bytes()).is_ok(),
+        ExportFormat::CHeader => file.write_all(codegen::c_header(export).as_bytes()).is_ok(),
+    };
+    if !written {
+        return Err(AppError::CouldNotCreateAtlasFile(path.to_path_buf()));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use crate::OptError;
+
+
+/// A range of glyph indices, including both ends.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GlyphIdRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+/// Parse a glyph index, like `7`, or a range of them, like `1-500`.
+pub fn parse_glyph_id_range(st: &str) -> Result<GlyphIdRange, OptError> {
+    let parse = |id: &str| id.trim().parse::<u32>().map_err(|_| OptError::InvalidGlyphIdRange(format!("{}", st)));
+    let range = match st.find('-') {
+        Some(dash) => GlyphIdRange { first: parse(&st[..dash])?, last: parse(&st[(dash + 1)..])? },
+        None => {
+            let id = parse(st)?;
+            GlyphIdRange { first: id, last: id }
+        }
+    };
+    if range.first > range.last {
+        return Err(OptError::InvalidGlyphIdRange(format!("{}", st)));
+    }
+
+    Ok(range)
+}
+
+/// The number of glyphs in a typeface. Glyph indices run from zero up to it.
+pub fn glyph_count(face: &freetype::face::Face) -> u32 {
+    face.raw().num_glyphs.max(0) as u32
+}
+
+/// Map each glyph of a typeface that its character map reaches to the code point reaching
+/// it. A glyph that several code points map to goes by the lowest of them.
+pub fn code_points_by_glyph(face: &freetype::face::Face) -> HashMap<u32, usize> {
+    let face_handle = face.raw() as *const freetype::ffi::FT_FaceRec as freetype::ffi::FT_Face;
+    let mut code_points = HashMap::new();
+    let mut glyph_index = 0;
+    // The face handle is owned by `face`, and walking the character map leaves the face as it
+    // is. The walk runs in increasing code point order, and ends at glyph index zero.
+    let mut code_point = unsafe { freetype::ffi::FT_Get_First_Char(face_handle, &mut glyph_index) };
+    while glyph_index != 0 {
+        code_points.entry(glyph_index).or_insert(code_point as usize);
+        code_point = unsafe { freetype::ffi::FT_Get_Next_Char(face_handle, code_point, &mut glyph_index) };
+    }
+
+    code_points
+}
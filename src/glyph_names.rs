@@ -0,0 +1,106 @@
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use crate::{AppError, GlyphMetrics};
+
+
+/// Load a file mapping glyph names to code points. Each line holds a name and the code point
+/// it stands for in hexadecimal, separated by whitespace, the way the `codepoints` file of
+/// Material Icons does. Blank lines and lines starting with `#` are skipped.
+pub fn load_glyph_name_map(path: &Path) -> Result<BTreeMap<String, usize>, AppError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(val) => val,
+        Err(_) => return Err(AppError::CouldNotReadGlyphNameMap(path.to_path_buf())),
+    };
+    let mut glyph_name_map = BTreeMap::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let name = fields.next();
+        let code_point = fields.next().map(|code_point| code_point.trim_start_matches("U+").trim_start_matches("0x"));
+        let code_point = match (name, code_point, fields.next()) {
+            (Some(name), Some(code_point), None) => usize::from_str_radix(code_point, 16).ok().map(|code_point| (name, code_point)),
+            _ => None,
+        };
+        match code_point {
+            Some((name, code_point)) => {
+                glyph_name_map.insert(String::from(name), code_point);
+            }
+            None => return Err(AppError::InvalidGlyphNameMapLine(path.to_path_buf(), line_number + 1)),
+        }
+    }
+
+    Ok(glyph_name_map)
+}
+
+/// Look up a glyph by the PostScript name the `post` table of a font gives it.
+fn glyph_index_by_post_name(face: &freetype::face::Face, name: &str) -> Option<u32> {
+    if !face.has_glyph_names() {
+        return None;
+    }
+    let name = CString::new(name).ok()?;
+    let face_handle = face.raw() as *const freetype::ffi::FT_FaceRec as freetype::ffi::FT_Face;
+    // The face handle is owned by `face`, and FreeType only reads the name. Glyph index zero
+    // stands for a name the font does not have.
+    let glyph_index = unsafe { freetype::ffi::FT_Get_Name_Index(face_handle, name.as_ptr() as *mut _) };
+
+    Some(glyph_index).filter(|&glyph_index| glyph_index != 0)
+}
+
+/// Find the glyph index of each name. A name in the glyph name map stands for the glyph its
+/// code point maps to, and any other name is looked up in the `post` table of the font. The
+/// path to the glyph name map, when one was given, only goes into the error messages.
+pub fn glyphs_by_name(
+    face: &freetype::face::Face,
+    names: &[String],
+    glyph_name_map: &BTreeMap<String, usize>, glyph_name_map_path: Option<&Path>) -> Result<Vec<(String, u32)>, AppError> {
+
+    names.iter().map(|name| {
+        if let Some(&code_point) = glyph_name_map.get(name) {
+            return match face.get_char_index(code_point) {
+                0 => Err(AppError::NoGlyphForMappedName(name.clone(), code_point)),
+                glyph_index => Ok((name.clone(), glyph_index)),
+            };
+        }
+        match (glyph_index_by_post_name(face, name), glyph_name_map_path) {
+            (Some(glyph_index), _) => Ok((name.clone(), glyph_index)),
+            (None, Some(path)) => Err(AppError::NoGlyphNamedInMap(name.clone(), path.to_path_buf())),
+            (None, None) => Err(AppError::NoGlyphNamed(name.clone())),
+        }
+    }).collect()
+}
+
+/// The metrics of a glyph picked by name, along with its key in the atlas.
+#[derive(Clone, Debug, Serialize)]
+struct NamedGlyph {
+    key: usize,
+    #[serde(flatten)]
+    metrics: GlyphMetrics,
+}
+
+/// Write the metrics of the glyphs picked by name to a JSON file, keyed by name, so that the
+/// glyphs can be looked up by name instead of by a private use code point.
+pub fn write_glyph_name_table(
+    path: &Path, named_keys: &[(String, usize)], glyphs: &BTreeMap<usize, GlyphMetrics>) -> Result<(), AppError> {
+
+    let table: BTreeMap<&str, NamedGlyph> = named_keys.iter()
+        .filter_map(|&(ref name, key)| {
+            glyphs.get(&key).map(|&metrics| (name.as_str(), NamedGlyph { key: key, metrics: metrics }))
+        })
+        .collect();
+    let file = match File::create(path) {
+        Ok(val) => val,
+        Err(_) => return Err(AppError::CouldNotCreateAtlasFile(path.to_path_buf())),
+    };
+    if serde_json::to_writer_pretty(file, &table).is_err() {
+        return Err(AppError::CouldNotCreateAtlasFile(path.to_path_buf()));
+    }
+
+    Ok(())
+}
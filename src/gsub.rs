@@ -0,0 +1,502 @@
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::path::Path;
+use crate::{AppError, OptError};
+
+
+extern "C" {
+    // The bindings of freetype-sys leave this one out.
+    fn FT_Load_Sfnt_Table(
+        face: freetype::ffi::FT_Face,
+        tag: freetype::ffi::FT_ULong,
+        offset: freetype::ffi::FT_Long,
+        buffer: *mut freetype::ffi::FT_Byte,
+        length: *mut freetype::ffi::FT_ULong
+    ) -> freetype::ffi::FT_Error;
+}
+
+pub fn parse_feature_tag(st: &str) -> Result<String, OptError> {
+    if st.len() == 4 && st.bytes().all(|byte| (0x20..=0x7E).contains(&byte)) {
+        Ok(String::from(st))
+    } else {
+        Err(OptError::InvalidFeatureTag(format!("{}", st)))
+    }
+}
+
+/// Copy the raw GSUB table out of a TrueType or OpenType font. Fonts without one, like
+/// bitmap fonts, have no features to apply.
+pub fn load_gsub_table(face: &freetype::face::Face) -> Option<Vec<u8>> {
+    let tag = u32::from_be_bytes(*b"GSUB") as freetype::ffi::FT_ULong;
+    let face_handle = face.raw() as *const freetype::ffi::FT_FaceRec as freetype::ffi::FT_Face;
+    let mut length: freetype::ffi::FT_ULong = 0;
+    // Asking for a table without a buffer gives its length. The face handle is owned by `face`,
+    // and loading a table leaves the face as it is.
+    let error = unsafe { FT_Load_Sfnt_Table(face_handle, tag, 0, std::ptr::null_mut(), &mut length) };
+    if error != 0 || length == 0 {
+        return None;
+    }
+    let mut table = vec![0 as u8; length as usize];
+    let error = unsafe { FT_Load_Sfnt_Table(face_handle, tag, 0, table.as_mut_ptr(), &mut length) };
+    if error != 0 {
+        return None;
+    }
+
+    Some(table)
+}
+
+/// The way a substitution replaces its input glyphs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubstitutionKind {
+    /// One glyph replaces one glyph.
+    Single,
+    /// A sequence of glyphs replaces one glyph.
+    Multiple,
+    /// Any one of a set of alternate glyphs replaces one glyph.
+    Alternate,
+    /// One glyph replaces a sequence of glyphs.
+    Ligature,
+}
+
+/// A single substitution of glyphs from the GSUB table of a font, by glyph index.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Substitution {
+    /// The tag of the feature the substitution belongs to.
+    pub feature: String,
+    pub kind: SubstitutionKind,
+    /// The glyphs replaced.
+    pub input: Vec<u32>,
+    /// The glyphs replacing the input. This is one glyph, except for multiple substitutions,
+    /// which give the sequence replacing the input, and alternate substitutions, which give
+    /// every alternate in the order the font lists them.
+    pub output: Vec<u32>,
+    /// Whether the substitution only applies in some context of surrounding glyphs, because
+    /// it is reached through a contextual lookup.
+    pub contextual: bool,
+}
+
+/// The GSUB table of a font, with every offset in it resolved against the start of the table.
+/// A malformed table reads as missing data, and the parts of it that are missing are left out.
+struct GsubTable<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> GsubTable<'a> {
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..(offset + 2))?;
+
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..(offset + 4))?;
+
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Read a 16 bit offset from the table at `base`, stored at `base + at`. A null offset
+    /// points at nothing.
+    fn offset_at(&self, base: usize, at: usize) -> Option<usize> {
+        match self.u16_at(base + at)? {
+            0 => None,
+            offset => Some(base + offset as usize),
+        }
+    }
+
+    /// Read an array of `count` 16 bit values starting at `offset`.
+    fn u16_array(&self, offset: usize, count: usize) -> Vec<u16> {
+        (0..count).filter_map(|index| self.u16_at(offset + 2 * index)).collect()
+    }
+
+    /// Read a count followed by that many 16 bit offsets from the table at `base`, stored at
+    /// `base + at`.
+    fn offset_array(&self, base: usize, at: usize) -> Vec<usize> {
+        let count = self.u16_at(base + at).unwrap_or(0) as usize;
+
+        (0..count).filter_map(|index| self.offset_at(base, at + 2 + 2 * index)).collect()
+    }
+
+    /// The glyphs of a coverage table, in coverage index order.
+    fn coverage(&self, offset: usize) -> Vec<u32> {
+        match self.u16_at(offset) {
+            Some(1) => {
+                let count = self.u16_at(offset + 2).unwrap_or(0) as usize;
+                self.u16_array(offset + 4, count).into_iter().map(|glyph| glyph as u32).collect()
+            }
+            Some(2) => {
+                let count = self.u16_at(offset + 2).unwrap_or(0) as usize;
+                let mut glyphs = vec![];
+                for index in 0..count {
+                    let record = offset + 4 + 6 * index;
+                    if let (Some(start), Some(end)) = (self.u16_at(record), self.u16_at(record + 2)) {
+                        glyphs.extend((start as u32)..(end as u32 + 1));
+                    }
+                }
+                glyphs
+            }
+            _ => vec![],
+        }
+    }
+
+    /// The glyphs of the coverage table a subtable at `base` points to at `base + at`.
+    fn coverage_at(&self, base: usize, at: usize) -> Vec<u32> {
+        self.offset_at(base, at).map_or(vec![], |offset| self.coverage(offset))
+    }
+
+    /// The lookup list indices of the features with any of the given tags, for each tag.
+    fn feature_lookups(&self, features: &[String]) -> Vec<(String, BTreeSet<usize>)> {
+        let feature_list = match self.offset_at(0, 6) {
+            Some(val) => val,
+            None => return vec![],
+        };
+        let count = self.u16_at(feature_list).unwrap_or(0) as usize;
+        features.iter().map(|feature| {
+            let mut lookups = BTreeSet::new();
+            for index in 0..count {
+                let record = feature_list + 2 + 6 * index;
+                if self.data.get(record..(record + 4)) != Some(feature.as_bytes()) {
+                    continue;
+                }
+                if let Some(table) = self.offset_at(feature_list, 2 + 6 * index + 4) {
+                    let lookup_count = self.u16_at(table + 2).unwrap_or(0) as usize;
+                    lookups.extend(self.u16_array(table + 4, lookup_count).into_iter().map(|lookup| lookup as usize));
+                }
+            }
+            (feature.clone(), lookups)
+        }).collect()
+    }
+
+    /// The lookup type and subtable offsets of a lookup in the lookup list.
+    fn lookup(&self, lookup_index: usize) -> Option<(u16, Vec<usize>)> {
+        let lookup_list = self.offset_at(0, 8)?;
+        let lookup = self.offset_at(lookup_list, 2 + 2 * lookup_index)?;
+
+        Some((self.u16_at(lookup)?, self.offset_array(lookup, 4)))
+    }
+}
+
+/// Walks the lookups of a feature, collecting their substitutions.
+struct LookupWalker<'a, 'b> {
+    table: &'b GsubTable<'a>,
+    feature: String,
+    /// The lookups already walked, with whether they were walked in a context. Lookups can
+    /// refer to each other through contextual lookups, so each one is walked only once.
+    visited: HashSet<(usize, bool)>,
+    substitutions: Vec<Substitution>,
+}
+
+impl<'a, 'b> LookupWalker<'a, 'b> {
+    fn walk_lookup(&mut self, lookup_index: usize, contextual: bool) {
+        if !self.visited.insert((lookup_index, contextual)) {
+            return;
+        }
+        if let Some((lookup_type, subtables)) = self.table.lookup(lookup_index) {
+            for subtable in subtables {
+                self.walk_subtable(lookup_type, subtable, contextual);
+            }
+        }
+    }
+
+    fn push(&mut self, kind: SubstitutionKind, input: Vec<u32>, output: Vec<u32>, contextual: bool) {
+        if output.is_empty() {
+            return;
+        }
+        self.substitutions.push(Substitution {
+            feature: self.feature.clone(),
+            kind: kind,
+            input: input,
+            output: output,
+            contextual: contextual,
+        });
+    }
+
+    fn walk_subtable(&mut self, lookup_type: u16, subtable: usize, contextual: bool) {
+        let table = self.table;
+        let format = table.u16_at(subtable).unwrap_or(0);
+        match (lookup_type, format) {
+            (1, 1) => {
+                let delta = table.u16_at(subtable + 4).unwrap_or(0);
+                for glyph in table.coverage_at(subtable, 2) {
+                    // The delta wraps around modulo 65536.
+                    let output = (glyph as u16).wrapping_add(delta) as u32;
+                    self.push(SubstitutionKind::Single, vec![glyph], vec![output], contextual);
+                }
+            }
+            (1, 2) => {
+                let count = table.u16_at(subtable + 4).unwrap_or(0) as usize;
+                let outputs = table.u16_array(subtable + 6, count);
+                for (glyph, &output) in table.coverage_at(subtable, 2).into_iter().zip(outputs.iter()) {
+                    self.push(SubstitutionKind::Single, vec![glyph], vec![output as u32], contextual);
+                }
+            }
+            (2, 1) | (3, 1) => {
+                let kind = if lookup_type == 2 { SubstitutionKind::Multiple } else { SubstitutionKind::Alternate };
+                let sets = table.offset_array(subtable, 4);
+                for (glyph, set) in table.coverage_at(subtable, 2).into_iter().zip(sets) {
+                    let count = table.u16_at(set).unwrap_or(0) as usize;
+                    let outputs = table.u16_array(set + 2, count).into_iter().map(|output| output as u32).collect();
+                    self.push(kind, vec![glyph], outputs, contextual);
+                }
+            }
+            (4, 1) => {
+                let sets = table.offset_array(subtable, 4);
+                for (glyph, set) in table.coverage_at(subtable, 2).into_iter().zip(sets) {
+                    for ligature in table.offset_array(set, 0) {
+                        let output = match table.u16_at(ligature) {
+                            Some(val) => val as u32,
+                            None => continue,
+                        };
+                        // The first component is the glyph in the coverage table.
+                        let count = table.u16_at(ligature + 2).unwrap_or(1).max(1) as usize;
+                        let mut input = vec![glyph];
+                        input.extend(table.u16_array(ligature + 4, count - 1).into_iter().map(|component| component as u32));
+                        self.push(SubstitutionKind::Ligature, input, vec![output], contextual);
+                    }
+                }
+            }
+            (5, _) | (6, _) => {
+                for lookup_index in self.nested_lookups(lookup_type, format, subtable) {
+                    self.walk_lookup(lookup_index, true);
+                }
+            }
+            (7, 1) => {
+                // An extension cannot point at another extension, so a malformed table that
+                // refers back to itself is not followed.
+                let extension_type = table.u16_at(subtable + 2).unwrap_or(0);
+                match table.u32_at(subtable + 4) {
+                    Some(offset) if extension_type != 7 && offset != 0 => {
+                        self.walk_subtable(extension_type, subtable + offset as usize, contextual);
+                    }
+                    _ => {}
+                }
+            }
+            (8, 1) => {
+                let backtrack_count = table.u16_at(subtable + 4).unwrap_or(0) as usize;
+                let lookahead = subtable + 6 + 2 * backtrack_count;
+                let lookahead_count = table.u16_at(lookahead).unwrap_or(0) as usize;
+                let substitutes = lookahead + 2 + 2 * lookahead_count;
+                let count = table.u16_at(substitutes).unwrap_or(0) as usize;
+                let outputs = table.u16_array(substitutes + 2, count);
+                for (glyph, &output) in table.coverage_at(subtable, 2).into_iter().zip(outputs.iter()) {
+                    self.push(SubstitutionKind::Single, vec![glyph], vec![output as u32], true);
+                }
+            }
+            // Unknown lookup types and formats have nothing to give.
+            _ => {}
+        }
+    }
+
+    /// The lookups a contextual or chained contextual subtable applies to the glyphs it
+    /// matches. The context itself is left out.
+    fn nested_lookups(&self, lookup_type: u16, format: u16, subtable: usize) -> Vec<usize> {
+        let table = self.table;
+        // The offset of the lookup records from the start of a rule, past its glyph sequences.
+        let records = |rule: usize| -> Option<(usize, usize)> {
+            if lookup_type == 5 {
+                let glyph_count = table.u16_at(rule)? as usize;
+                let count = table.u16_at(rule + 2)? as usize;
+                Some((rule + 4 + 2 * glyph_count.saturating_sub(1), count))
+            } else {
+                let backtrack = rule;
+                let input = backtrack + 2 + 2 * table.u16_at(backtrack)? as usize;
+                let lookahead = input + 2 + 2 * (table.u16_at(input)? as usize).saturating_sub(1);
+                let count = lookahead + 2 + 2 * table.u16_at(lookahead)? as usize;
+                Some((count + 2, table.u16_at(count)? as usize))
+            }
+        };
+        let mut lookup_records = vec![];
+        match (lookup_type, format) {
+            (_, 1) | (_, 2) => {
+                // The rule sets come after the coverage table, and the class definitions of
+                // the second format.
+                let sets_at = match (lookup_type, format) {
+                    (_, 1) => 4,
+                    (5, _) => 6,
+                    _ => 10,
+                };
+                for set in table.offset_array(subtable, sets_at) {
+                    for rule in table.offset_array(set, 0) {
+                        lookup_records.extend(records(rule));
+                    }
+                }
+            }
+            (5, 3) => {
+                let glyph_count = table.u16_at(subtable + 2).unwrap_or(0) as usize;
+                let count = table.u16_at(subtable + 4).unwrap_or(0) as usize;
+                lookup_records.push((subtable + 6 + 2 * glyph_count, count));
+            }
+            (6, 3) => {
+                let backtrack = subtable + 2;
+                let input = backtrack + 2 + 2 * table.u16_at(backtrack).unwrap_or(0) as usize;
+                let lookahead = input + 2 + 2 * table.u16_at(input).unwrap_or(0) as usize;
+                let count = lookahead + 2 + 2 * table.u16_at(lookahead).unwrap_or(0) as usize;
+                lookup_records.push((count + 2, table.u16_at(count).unwrap_or(0) as usize));
+            }
+            _ => {}
+        }
+
+        // Each record holds the position in the sequence to apply a lookup at, and the lookup.
+        let mut lookups = BTreeSet::new();
+        for (offset, count) in lookup_records {
+            for index in 0..count {
+                if let Some(lookup_index) = table.u16_at(offset + 4 * index + 2) {
+                    lookups.insert(lookup_index as usize);
+                }
+            }
+        }
+
+        lookups.into_iter().collect()
+    }
+}
+
+/// Collect the substitutions of every lookup of the given features from a GSUB table, in the
+/// order the features are given and the lookups are listed. Lookups applied by contextual
+/// lookups give substitutions marked as contextual. Only the substitution lookup types of
+/// OpenType are understood, and anything else in the table is skipped.
+pub fn feature_substitutions(gsub: &[u8], features: &[String]) -> Vec<Substitution> {
+    let table = GsubTable { data: gsub };
+    let mut substitutions = vec![];
+    for (feature, lookups) in table.feature_lookups(features) {
+        let mut walker = LookupWalker {
+            table: &table,
+            feature: feature,
+            visited: HashSet::new(),
+            substitutions: vec![],
+        };
+        for lookup_index in lookups {
+            walker.walk_lookup(lookup_index, false);
+        }
+        substitutions.extend(walker.substitutions);
+    }
+
+    substitutions
+}
+
+/// A substitution as exported, with the glyphs given by their keys in the atlas.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AtlasSubstitution {
+    pub feature: String,
+    pub kind: SubstitutionKind,
+    /// The keys of the glyphs replaced.
+    pub input: Vec<usize>,
+    /// The keys of the glyphs replacing the input, as in `Substitution::output`.
+    pub output: Vec<usize>,
+    #[serde(default, skip_serializing_if = "crate::is_false")]
+    pub contextual: bool,
+}
+
+/// The substitutions of the requested features among the glyphs of an atlas. This gets
+/// written next to the atlas file, so text can be shaped with the glyphs in the atlas.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SubstitutionTable {
+    /// The tags of the features, as requested.
+    pub features: Vec<String>,
+    /// The substitutions, keyed the same way as the glyphs of the atlas: by code point, or
+    /// by glyph index for glyphs that no code point in the atlas maps to.
+    pub substitutions: Vec<AtlasSubstitution>,
+}
+
+impl SubstitutionTable {
+    /// Build the table for the substitutions that can happen in text drawn with the glyphs of
+    /// an atlas. These are the ones whose input glyphs are all in the atlas, either through
+    /// the code points in `code_points`, which maps glyph indices to the code points they are
+    /// sampled under, or as the output of another such substitution.
+    pub fn new(features: &[String], substitutions: Vec<Substitution>, code_points: &HashMap<u32, usize>) -> SubstitutionTable {
+        let mut reachable: HashSet<u32> = code_points.keys().cloned().collect();
+        let mut kept = vec![false; substitutions.len()];
+        loop {
+            let mut changed = false;
+            for (index, substitution) in substitutions.iter().enumerate() {
+                if kept[index] || !substitution.input.iter().all(|glyph| reachable.contains(glyph)) {
+                    continue;
+                }
+                kept[index] = true;
+                reachable.extend(substitution.output.iter().cloned());
+                changed = true;
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let key = |glyph: &u32| match code_points.get(glyph) {
+            Some(&code_point) => code_point,
+            None => crate::glyph_index_key(*glyph),
+        };
+        let substitutions = substitutions.into_iter().zip(kept)
+            .filter(|&(_, kept)| kept)
+            .map(|(substitution, _)| AtlasSubstitution {
+                feature: substitution.feature,
+                kind: substitution.kind,
+                input: substitution.input.iter().map(key).collect(),
+                output: substitution.output.iter().map(key).collect(),
+                contextual: substitution.contextual,
+            })
+            .collect();
+
+        SubstitutionTable {
+            features: features.to_vec(),
+            substitutions: substitutions,
+        }
+    }
+
+    /// The keys of every glyph in the table without a code point, which get sampled by
+    /// glyph index.
+    pub fn glyph_index_keys(&self) -> BTreeSet<usize> {
+        self.substitutions.iter()
+            .flat_map(|substitution| substitution.input.iter().chain(substitution.output.iter()))
+            .filter(|&&key| crate::is_glyph_index_key(key))
+            .cloned()
+            .collect()
+    }
+
+    /// Write the table out to a JSON file.
+    pub fn write_to_file(&self, path: &Path) -> Result<(), AppError> {
+        let file = match File::create(path) {
+            Ok(val) => val,
+            Err(_) => return Err(AppError::CouldNotCreateAtlasFile(path.to_path_buf())),
+        };
+        if serde_json::to_writer_pretty(file, self).is_err() {
+            return Err(AppError::CouldNotCreateAtlasFile(path.to_path_buf()));
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// A GSUB table with a feature whose only lookup is an extension lookup, with a single
+    /// extension subtable of the given type at `offset` bytes from itself.
+    fn extension_gsub(feature: &[u8; 4], extension_type: u16, offset: u32) -> Vec<u8> {
+        let mut data = vec![];
+        // The header: version 1.0, no script list, then the feature and lookup lists.
+        data.extend(&[0, 1, 0, 0, 0, 0, 0, 10, 0, 24]);
+        // The feature list, with one feature record, and its feature table listing lookup 0.
+        data.extend(&[0, 1]);
+        data.extend(feature);
+        data.extend(&[0, 8, 0, 0, 0, 1, 0, 0]);
+        // The lookup list with one lookup, of type 7 with one subtable.
+        data.extend(&[0, 1, 0, 4, 0, 7, 0, 0, 0, 1, 0, 8]);
+        // The extension subtable.
+        data.extend(&[0, 1]);
+        data.extend(&extension_type.to_be_bytes());
+        data.extend(&offset.to_be_bytes());
+
+        data
+    }
+
+    #[test]
+    fn a_self_referencing_extension_subtable_has_no_substitutions() {
+        let features = vec![String::from("liga")];
+        for &(extension_type, offset) in &[(7, 0), (1, 0)] {
+            let gsub = extension_gsub(b"liga", extension_type, offset);
+            assert!(feature_substitutions(&gsub, &features).is_empty());
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+use crate::{AppError, OrderedAtlasMetadata};
+
+
+/// The shell input options for `fontgen inspect`.
+#[derive(Debug, StructOpt)]
+pub struct InspectOpt {
+    /// The path to the bitmap font atlas file to inspect.
+    #[structopt(parse(from_os_str))]
+    pub atlas_path: PathBuf,
+    /// Print the metadata as JSON instead of as a table.
+    #[structopt(long = "json")]
+    pub json: bool,
+    /// Only print the glyphs for the given code points. A code point can be written in
+    /// decimal, or in hexadecimal as `0x41` or `U+0041`.
+    #[structopt(long = "code-point")]
+    #[structopt(raw(use_delimiter = "true"))]
+    #[structopt(parse(try_from_str = "crate::parse_code_point"))]
+    pub code_points: Vec<usize>,
+}
+
+/// Collect the atlas metadata ordered by code point, keeping only the glyphs selected by
+/// the code point filter.
+fn create_report(metadata: &bmfa::BitmapFontAtlasMetadata, code_points: &[usize]) -> OrderedAtlasMetadata {
+    let mut report = OrderedAtlasMetadata::from(metadata);
+    if !code_points.is_empty() {
+        report.glyph_metadata = report.glyph_metadata.into_iter()
+            .filter(|&(code_point, _)| code_points.contains(&code_point))
+            .collect();
+    }
+
+    report
+}
+
+/// Print the atlas metadata followed by a table with one row per glyph.
+fn print_report(report: &OrderedAtlasMetadata) {
+    println!("origin:          {}", crate::origin_name(report.origin));
+    println!("width:           {}", report.width);
+    println!("height:          {}", report.height);
+    println!("columns:         {}", report.columns);
+    println!("rows:            {}", report.rows);
+    println!("padding:         {}", report.padding);
+    println!("slot glyph size: {}", report.slot_glyph_size);
+    println!("glyph size:      {}", report.glyph_size);
+    println!("glyphs:          {}", report.glyph_metadata.len());
+    println!();
+    println!(
+        "{:>10} {:>4} {:>4} {:>6} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "code point", "char", "row", "column", "x_min", "y_min", "width", "height", "y_offset"
+    );
+    for (code_point, glyph) in report.glyph_metadata.iter() {
+        let character = match std::char::from_u32(*code_point as u32) {
+            Some(ch) if !ch.is_control() && !ch.is_whitespace() => ch,
+            _ => ' ',
+        };
+        println!(
+            "{:>10} {:>4} {:>4} {:>6} {:>10.6} {:>10.6} {:>10.6} {:>10.6} {:>10.6}",
+            code_point, character, glyph.row, glyph.column,
+            glyph.x_min, glyph.y_min, glyph.width, glyph.height, glyph.y_offset
+        );
+    }
+}
+
+/// Run the `inspect` subcommand.
+pub fn run_inspect(opt: &InspectOpt) -> Result<(), Box<dyn std::error::Error>> {
+    let atlas = match bmfa::load(&opt.atlas_path) {
+        Ok(val) => val,
+        Err(_) => {
+            return Err(Box::new(AppError::CouldNotOpenAtlasFile(opt.atlas_path.clone())));
+        }
+    };
+
+    let report = create_report(&atlas.metadata, &opt.code_points);
+    if opt.json {
+        let json = serde_json::to_string_pretty(&report)?;
+        println!("{}", json);
+    } else {
+        print_report(&report);
+    }
+
+    Ok(())
+}
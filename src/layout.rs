@@ -0,0 +1,246 @@
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use crate::{AtlasSpec, GlyphTable};
+
+
+/// A rectangle of pixels inside a glyph image, measured from the top left corner of the image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GlyphBounds {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl GlyphBounds {
+    /// The bounds covering the whole glyph image.
+    fn image(glyph_tab: &GlyphTable, code_point: usize) -> GlyphBounds {
+        GlyphBounds {
+            x: 0,
+            y: 0,
+            width: glyph_tab.width[&code_point].max(0) as usize,
+            height: glyph_tab.rows[&code_point].max(0) as usize,
+        }
+    }
+
+    /// Find the smallest rectangle holding every pixel of a glyph image with nonzero
+    /// coverage. A glyph without any ink, like a blank glyph, gets an empty rectangle.
+    fn ink(glyph_tab: &GlyphTable, code_point: usize) -> GlyphBounds {
+        let image = GlyphBounds::image(glyph_tab, code_point);
+        let pitch = glyph_tab.pitch[&code_point].abs() as usize;
+        let data = &glyph_tab.buffer[&code_point].data;
+        let mut min_x = image.width;
+        let mut min_y = image.height;
+        let mut max_x = 0;
+        let mut max_y = 0;
+        for y in 0..image.height {
+            for x in 0..image.width {
+                if data[y * pitch + x] != 0 {
+                    min_x = cmp::min(min_x, x);
+                    min_y = cmp::min(min_y, y);
+                    max_x = cmp::max(max_x, x + 1);
+                    max_y = cmp::max(max_y, y + 1);
+                }
+            }
+        }
+
+        if min_x >= max_x || min_y >= max_y {
+            return GlyphBounds { x: 0, y: 0, width: 0, height: 0 };
+        }
+
+        GlyphBounds {
+            x: min_x,
+            y: min_y,
+            width: max_x - min_x,
+            height: max_y - min_y,
+        }
+    }
+
+    /// Copy the pixels inside the bounds out of a glyph image, row by row. The colors of
+    /// a color glyph follow its coverage.
+    fn pixels(&self, glyph_tab: &GlyphTable, code_point: usize) -> Vec<u8> {
+        let pitch = glyph_tab.pitch[&code_point].abs() as usize;
+        let data = &glyph_tab.buffer[&code_point].data;
+        let mut pixels = Vec::with_capacity(self.width * self.height);
+        for y in self.y..(self.y + self.height) {
+            pixels.extend_from_slice(&data[(y * pitch + self.x)..(y * pitch + self.x + self.width)]);
+        }
+        if let Some(ref color) = glyph_tab.buffer[&code_point].color {
+            for y in self.y..(self.y + self.height) {
+                pixels.extend_from_slice(&color[(4 * (y * pitch + self.x))..(4 * (y * pitch + self.x + self.width))]);
+            }
+        }
+
+        pixels
+    }
+}
+
+/// The position of a single glyph image in the atlas image.
+#[derive(Copy, Clone, Debug)]
+pub struct GlyphPlacement {
+    /// The code point of the glyph.
+    pub code_point: usize,
+    /// The row of the atlas the glyph sits in.
+    pub row: usize,
+    /// The position of the glyph along its row.
+    pub column: usize,
+    /// The horizontal position of the left edge of the glyph rectangle in the atlas, in pixels.
+    pub x: usize,
+    /// The vertical position of the top edge of the glyph rectangle from the top of the atlas,
+    /// in pixels. The glyph image sits `padding / 2` pixels inside the rectangle.
+    pub y: usize,
+    /// The part of the glyph image copied into the atlas.
+    pub bounds: GlyphBounds,
+    /// The width of the region of the atlas reserved for the glyph, starting at `x`. Any part
+    /// of the glyph image reaching past it gets clipped.
+    pub cell_width: usize,
+    /// The height of the region of the atlas reserved for the glyph, starting at `y`.
+    pub cell_height: usize,
+    /// The code point of the glyph whose image this glyph shares, if it has no image of
+    /// its own in the atlas.
+    pub shares_image_with: Option<usize>,
+}
+
+/// The position of every sampled glyph image in the atlas image.
+pub struct AtlasLayout {
+    /// The atlas specification, with the atlas dimensions adjusted to fit the layout.
+    pub spec: AtlasSpec,
+    /// Whether each glyph was trimmed down to its ink and packed, instead of being
+    /// placed in a grid slot.
+    pub trimmed: bool,
+    /// The placement of each glyph, ordered by code point.
+    pub placements: Vec<GlyphPlacement>,
+    /// The number of glyphs sharing the image of another glyph.
+    pub shared_glyphs: usize,
+    /// The area in pixels of the glyph rectangles left out of the atlas because their
+    /// images are shared.
+    pub saved_area: usize,
+}
+
+/// Place each glyph image in its own slot of a fixed grid. The slot for a code point is
+/// determined by its position in the character set, and the whole glyph image goes into it.
+pub fn grid_layout(glyph_tab: &GlyphTable, spec: AtlasSpec) -> AtlasLayout {
+    let placements = glyph_tab.buffer.keys().map(|&code_point| {
+        let order = code_point - 32;
+        let row = order / spec.columns;
+        let column = order % spec.columns;
+        let (x, y) = spec.slot_origin(column, row);
+
+        GlyphPlacement {
+            code_point: code_point,
+            row: row,
+            column: column,
+            x: x,
+            y: y,
+            bounds: GlyphBounds::image(glyph_tab, code_point),
+            cell_width: spec.slot_glyph_size,
+            cell_height: spec.slot_glyph_size,
+            shares_image_with: None,
+        }
+    }).collect();
+
+    AtlasLayout {
+        spec: spec,
+        trimmed: false,
+        placements: placements,
+        shared_glyphs: 0,
+        saved_area: 0,
+    }
+}
+
+/// Trim each glyph image down to the rectangle holding its ink, and pack the rectangles
+/// into shelves running across the atlas, tallest glyphs first. Each rectangle keeps
+/// `padding` pixels around the ink, and the extrusion and spacing go around the rectangles.
+/// The atlas keeps its width, and its height shrinks to the height of the shelves.
+///
+/// With `dedupe`, a glyph that maps to the same glyph index in the font as a glyph with a
+/// lower code point, or whose ink is identical to the ink of such a glyph, does not get
+/// packed. It shares the rectangle of the first glyph instead.
+pub fn trimmed_layout(glyph_tab: &GlyphTable, spec: AtlasSpec, dedupe: bool) -> AtlasLayout {
+    let mut glyphs = vec![];
+    let mut aliases = vec![];
+    let mut glyph_indices = HashMap::new();
+    let mut images = HashMap::new();
+    for &code_point in glyph_tab.buffer.keys() {
+        let bounds = GlyphBounds::ink(glyph_tab, code_point);
+        if dedupe {
+            let glyph_index = glyph_tab.glyph_index.get(&code_point);
+            if let Some(&original) = glyph_index.and_then(|glyph_index| glyph_indices.get(glyph_index)) {
+                aliases.push((code_point, bounds, original));
+                continue;
+            }
+            let image = (bounds.width, bounds.height, bounds.pixels(glyph_tab, code_point));
+            if let Some(&original) = images.get(&image) {
+                aliases.push((code_point, bounds, original));
+                continue;
+            }
+            if let Some(&glyph_index) = glyph_index {
+                glyph_indices.insert(glyph_index, code_point);
+            }
+            images.insert(image, code_point);
+        }
+        glyphs.push((code_point, bounds));
+    }
+    glyphs.sort_by(|a, b| b.1.height.cmp(&a.1.height).then(a.0.cmp(&b.0)));
+
+    let mut placements = Vec::with_capacity(glyphs.len());
+    let mut x = 0;
+    let mut y = 0;
+    let mut row = 0;
+    let mut column = 0;
+    let mut shelf_height = 0;
+    let gutter = 2 * spec.extrude;
+    for (code_point, bounds) in glyphs.into_iter() {
+        let width = bounds.width + spec.padding;
+        let height = bounds.height + spec.padding;
+        if x > 0 && x + width + gutter > spec.width {
+            // Start a new shelf underneath the current one.
+            x = 0;
+            y += shelf_height + spec.spacing;
+            row += 1;
+            column = 0;
+            shelf_height = 0;
+        }
+
+        placements.push(GlyphPlacement {
+            code_point: code_point,
+            row: row,
+            column: column,
+            x: x + spec.extrude,
+            y: y + spec.extrude,
+            bounds: bounds,
+            cell_width: width,
+            cell_height: height,
+            shares_image_with: None,
+        });
+        x += width + gutter + spec.spacing;
+        column += 1;
+        shelf_height = cmp::max(shelf_height, height + gutter);
+    }
+
+    let mut placements: BTreeMap<usize, GlyphPlacement> = placements.into_iter()
+        .map(|placement| (placement.code_point, placement))
+        .collect();
+    let mut saved_area = 0;
+    for &(code_point, bounds, original) in aliases.iter() {
+        // The ink is the same, but it can sit somewhere else in the glyph image, so the
+        // glyph keeps its own bounds.
+        let mut placement = placements[&original];
+        placement.code_point = code_point;
+        placement.bounds = bounds;
+        placement.shares_image_with = Some(original);
+        saved_area += placement.cell_width * placement.cell_height;
+        placements.insert(code_point, placement);
+    }
+
+    let mut trimmed_spec = spec;
+    trimmed_spec.height = cmp::max(1, y + shelf_height);
+
+    AtlasLayout {
+        spec: trimmed_spec,
+        trimmed: true,
+        placements: placements.into_iter().map(|(_, placement)| placement).collect(),
+        shared_glyphs: aliases.len(),
+        saved_area: saved_area,
+    }
+}
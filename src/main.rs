@@ -1,16 +1,44 @@
 extern crate bmfa;
 extern crate freetype;
 extern crate image;
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
 extern crate structopt;
+extern crate zip;
 
+mod atlas_file;
+mod cache;
+mod canvas;
+mod codegen;
+mod export;
+mod glyph_ids;
+mod glyph_names;
+mod gsub;
+mod inspect;
+mod layout;
+mod mipmap;
+mod mono;
+mod overlay;
+mod preview;
+mod strikes;
+mod texture;
+mod unpack;
+mod watch;
 
 use bmfa::{BitmapFontAtlas, BitmapFontAtlasMetadata, GlyphMetadata};
 use freetype::Library;
-use std::collections::HashMap;
+use freetype::bitmap::PixelMode;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::error;
 use std::fmt;
+use std::fs;
+use std::fs::File;
 use std::mem;
-use std::path::PathBuf;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::thread;
 use structopt::StructOpt;
 
 
@@ -35,13 +63,19 @@ struct AtlasSpec {
     slot_glyph_size: usize,
     /// The size of a glyph inside the slot, leaving room for padding for outlines.
     glyph_size: usize,
+    /// The number of pixels each glyph rectangle is extended by on every side, by copying
+    /// its edge pixels outward.
+    extrude: usize,
+    /// The number of empty pixels between neighbouring glyph slots, outside of the extrusion.
+    spacing: usize,
 }
 
 impl AtlasSpec {
     fn new(
         origin: bmfa::Origin,
         width: usize, height: usize, rows: usize, columns: usize,
-        padding: usize, slot_glyph_size: usize, glyph_size: usize) -> AtlasSpec {
+        padding: usize, slot_glyph_size: usize, glyph_size: usize,
+        extrude: usize, spacing: usize) -> AtlasSpec {
 
         AtlasSpec {
             origin: origin,
@@ -52,20 +86,67 @@ impl AtlasSpec {
             padding: padding,
             slot_glyph_size: slot_glyph_size,
             glyph_size: glyph_size,
+            extrude: extrude,
+            spacing: spacing,
         }
     }
+
+    /// The top left corner of the grid slot in a column and a row, in pixels. Neighbouring
+    /// slots are separated by the extrusion of both slots and the spacing.
+    fn slot_origin(&self, column: usize, row: usize) -> (usize, usize) {
+        let stride = self.slot_glyph_size + 2 * self.extrude + self.spacing;
+
+        (self.extrude + column * stride, self.extrude + row * stride)
+    }
 }
 
 /// A `GlyphImage` is a bitmapped representation of a single font glyph.
 #[derive(Clone)]
 struct GlyphImage {
     data: Vec<u8>,
+    /// The premultiplied RGBA color of each pixel of a color glyph, four bytes for each byte
+    /// of coverage in `data`. Other glyphs take their color from the text they are drawn in.
+    color: Option<Vec<u8>>,
 }
 
 impl GlyphImage {
     fn new(data: Vec<u8>) -> GlyphImage {
         GlyphImage {
             data: data,
+            color: None,
+        }
+    }
+
+    fn with_color(data: Vec<u8>, color: Vec<u8>) -> GlyphImage {
+        GlyphImage {
+            data: data,
+            color: Some(color),
+        }
+    }
+}
+
+/// The way each glyph image is rasterized.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum GlyphRendering {
+    /// Anti-aliased coverage.
+    Coverage,
+    /// One bit per pixel, from the monochrome rasterizer of FreeType.
+    Mono,
+    /// One bit per pixel, from anti-aliased coverage cut off at a threshold. Pixels with at
+    /// least the threshold coverage are set.
+    Threshold(u8),
+    /// Anti-aliased coverage, with color glyphs drawn in their own colors.
+    Color,
+}
+
+impl GlyphRendering {
+    /// A name for the rendering, used in cache keys.
+    fn name(self) -> String {
+        match self {
+            GlyphRendering::Coverage => String::from("coverage"),
+            GlyphRendering::Mono => String::from("mono"),
+            GlyphRendering::Threshold(threshold) => format!("threshold-{}", threshold),
+            GlyphRendering::Color => String::from("color"),
         }
     }
 }
@@ -74,15 +155,48 @@ impl GlyphImage {
 /// for each glyph to be used in the construction of the final bitmap atlas.
 struct GlyphTable {
     /// The height of a glyph in pixels.
-    rows: Vec<i32>,
+    rows: HashMap<usize, i32>,
     /// The width of a row in a glyph in pixels.
-    width: Vec<i32>,
+    width: HashMap<usize, i32>,
     /// The number of bytes per row in a glyph.
-    pitch: Vec<i32>,
+    pitch: HashMap<usize, i32>,
     /// The offset in pixels of a character from the baseline.
-    y_min: Vec<i64>,
-    /// A table holding the individual bitmap images for each glyph.
-    buffer: HashMap<usize, GlyphImage>,
+    y_min: HashMap<usize, i64>,
+    /// The offset in pixels of the left edge of a character from the pen position.
+    x_min: HashMap<usize, i64>,
+    /// The distance in pixels the pen advances after a character.
+    advance: HashMap<usize, i64>,
+    /// The horizontal position in pixels of the pen in vertical text, from the pen position
+    /// in horizontal text.
+    vertical_origin_x: HashMap<usize, i64>,
+    /// The vertical position in pixels of the pen in vertical text, up from the baseline.
+    vertical_origin_y: HashMap<usize, i64>,
+    /// The distance in pixels the pen advances down after a character in vertical text.
+    vertical_advance: HashMap<usize, i64>,
+    /// The index of each glyph in the font, including the space.
+    glyph_index: HashMap<usize, u32>,
+    /// The code point the character map of the font maps to each glyph keyed by glyph index,
+    /// for the glyphs it maps any code point to.
+    mapped_code_point: HashMap<usize, usize>,
+    /// A table holding the individual bitmap images for each glyph, ordered by code point.
+    buffer: BTreeMap<usize, GlyphImage>,
+}
+
+/// The first key of the glyphs sampled by glyph index instead of by code point, like the
+/// ligatures and alternates substituted in by GSUB features. These glyphs are keyed by their
+/// glyph index past this point, in the Supplementary Private Use Area-A, so their keys never
+/// clash with the code points sampled through the character map, and still make valid
+/// characters in generated source code.
+const GLYPH_INDEX_KEY_BASE: usize = 0xF0000;
+
+/// The key of a glyph sampled by glyph index.
+fn glyph_index_key(glyph_index: u32) -> usize {
+    GLYPH_INDEX_KEY_BASE + glyph_index as usize
+}
+
+/// Whether a glyph is keyed by its glyph index instead of by a code point.
+fn is_glyph_index_key(key: usize) -> bool {
+    key >= GLYPH_INDEX_KEY_BASE
 }
 
 /// Sample a single bitmap image for a single glyph from a font. The FreeType library interns
@@ -100,10 +214,103 @@ fn create_glyph_image(glyph: &freetype::glyph_slot::GlyphSlot) -> GlyphImage {
     GlyphImage::new(glyph_data)
 }
 
+/// Copy a packed glyph image out of FreeType, unpacking it to one byte per pixel. FreeType
+/// packs monochrome bitmaps, and the 2 and 4 bit gray bitmaps of some bitmap fonts, several
+/// pixels to a byte with the leftmost pixel in the most significant bits, so the unpacked image
+/// has a pitch of its width. Each gray level is scaled up to the full range of a byte.
+fn create_packed_glyph_image(glyph: &freetype::glyph_slot::GlyphSlot, bits_per_pixel: usize) -> GlyphImage {
+    let bitmap = glyph.bitmap();
+    let rows = bitmap.rows() as usize;
+    let width = bitmap.width() as usize;
+    let pitch = bitmap.pitch().abs() as usize;
+    let buffer = bitmap.buffer();
+    let pixels_per_byte = 8 / bits_per_pixel;
+    let max_value = (1 << bits_per_pixel) - 1;
+
+    let mut glyph_data = vec![0 as u8; rows * width];
+    for y in 0..rows {
+        for x in 0..width {
+            let byte = buffer[y * pitch + x / pixels_per_byte] as usize;
+            let shift = 8 - bits_per_pixel * (x % pixels_per_byte + 1);
+            let value = (byte >> shift) & max_value;
+            glyph_data[y * width + x] = (value * 255 / max_value) as u8;
+        }
+    }
+
+    GlyphImage::new(glyph_data)
+}
+
+/// Copy a color glyph image out of FreeType. Color glyphs come either as BGRA bitmaps from
+/// CBDT or sbix strikes, or from the COLR layers of the glyph, which FreeType composites in
+/// the colors of the default palette. The coverage of each pixel is its alpha, and the color
+/// is kept premultiplied. The image has a pitch of its width.
+fn create_color_glyph_image(glyph: &freetype::glyph_slot::GlyphSlot) -> GlyphImage {
+    let bitmap = glyph.bitmap();
+    let rows = bitmap.rows() as usize;
+    let width = bitmap.width() as usize;
+    let pitch = bitmap.pitch().abs() as usize;
+    let buffer = bitmap.buffer();
+
+    let mut glyph_data = vec![0 as u8; rows * width];
+    let mut color_data = vec![0 as u8; 4 * rows * width];
+    for y in 0..rows {
+        for x in 0..width {
+            let pixel = &buffer[(y * pitch + 4 * x)..(y * pitch + 4 * x + 4)];
+            let index = y * width + x;
+            glyph_data[index] = pixel[3];
+            color_data[4 * index] = pixel[2];
+            color_data[4 * index + 1] = pixel[1];
+            color_data[4 * index + 2] = pixel[0];
+            color_data[4 * index + 3] = pixel[3];
+        }
+    }
+
+    GlyphImage::with_color(glyph_data, color_data)
+}
+
+/// Scale a color glyph drawn in a bitmap strike to the glyph size. Color bitmaps are only
+/// drawn at a few sizes, so unlike other glyphs copied from a strike, they get resampled.
+/// The top edge of the glyph stays the same distance from the baseline, relative to its size.
+fn scale_color_glyph(sample: GlyphSample, scale: f64) -> GlyphSample {
+    let width = (sample.width as f64 * scale).round() as u32;
+    let rows = (sample.rows as f64 * scale).round() as u32;
+    let source = match sample.image.color {
+        Some(ref color) if width > 0 && rows > 0 => {
+            image::RgbaImage::from_raw(sample.width as u32, sample.rows as u32, color.clone())
+        }
+        _ => None,
+    };
+    let source = match source {
+        Some(val) => val,
+        None => return sample,
+    };
+
+    let color_data = image::imageops::resize(&source, width, rows, image::FilterType::Triangle).into_raw();
+    let glyph_data = color_data.chunks(4).map(|pixel| pixel[3]).collect();
+    let top = ((sample.y_min + sample.rows as i64) as f64 * scale).round() as i64;
+
+    GlyphSample {
+        code_point: sample.code_point,
+        rows: rows as i32,
+        width: width as i32,
+        pitch: width as i32,
+        y_min: top - rows as i64,
+        x_min: (sample.x_min as f64 * scale).round() as i64,
+        advance: (sample.advance as f64 * scale).round() as i64,
+        vertical_origin_x: (sample.vertical_origin_x as f64 * scale).round() as i64,
+        vertical_origin_y: (sample.vertical_origin_y as f64 * scale).round() as i64,
+        vertical_advance: (sample.vertical_advance as f64 * scale).round() as i64,
+        image: GlyphImage::with_color(glyph_data, color_data),
+    }
+}
+
 
 #[derive(Copy, Clone, Debug)]
 enum SampleTypefaceError {
+    InitLibrary(freetype::error::Error),
+    OpenFace(freetype::error::Error),
     SetPixelSize(freetype::error::Error, usize, usize),
+    SelectStrike(freetype::error::Error, usize),
     LoadCharacter(freetype::error::Error, usize),
     RenderCharacter(freetype::error::Error, usize),
     GetGlyphImage(freetype::error::Error, usize),
@@ -112,12 +319,30 @@ enum SampleTypefaceError {
 impl fmt::Display for SampleTypefaceError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            SampleTypefaceError::InitLibrary(_) => {
+                write!(f, "The FreeType library failed to initialize.")
+            }
+            SampleTypefaceError::OpenFace(_) => {
+                write!(f, "The FreeType library failed to open the typeface.")
+            }
             SampleTypefaceError::SetPixelSize(_, code_point, pixels) => {
                 write!(
                     f, "The FreeType library failed to set the size of glyph {} to {} pixels.",
                     code_point, pixels
                 )
             }
+            SampleTypefaceError::SelectStrike(_, pixels) => {
+                write!(
+                    f, "The FreeType library failed to select the {} pixel bitmap strike.",
+                    pixels
+                )
+            }
+            SampleTypefaceError::LoadCharacter(_, key) if is_glyph_index_key(key) => {
+                write!(
+                    f, "The FreeType library failed to load the glyph with glyph index {}.",
+                    key - GLYPH_INDEX_KEY_BASE
+                )
+            }
             SampleTypefaceError::LoadCharacter(_, code_point) => {
                 write!(
                     f, "The FreeType library failed to load the character with code point {}.",
@@ -143,7 +368,10 @@ impl fmt::Display for SampleTypefaceError {
 impl error::Error for SampleTypefaceError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
+            &SampleTypefaceError::InitLibrary(ref e) => Some(e),
+            &SampleTypefaceError::OpenFace(ref e) => Some(e),
             &SampleTypefaceError::SetPixelSize(ref e,_,_) => Some(e),
+            &SampleTypefaceError::SelectStrike(ref e,_) => Some(e),
             &SampleTypefaceError::LoadCharacter(ref e,_) => Some(e),
             &SampleTypefaceError::RenderCharacter(ref e, _) => Some(e),
             &SampleTypefaceError::GetGlyphImage(ref e,_) => Some(e),
@@ -151,48 +379,81 @@ impl error::Error for SampleTypefaceError {
     }
 }
 
-/// Generate the glyph image for each individual glyph slot in the typeface to be
-/// mapped into the final atlas image.
-fn sample_typeface(
-    face: freetype::face::Face, spec: AtlasSpec) -> Result<GlyphTable, SampleTypefaceError> {
+/// The parameters and bitmap image sampled for a single glyph.
+struct GlyphSample {
+    /// The code point of the glyph.
+    code_point: usize,
+    /// The height of the glyph in pixels.
+    rows: i32,
+    /// The width of a row in the glyph in pixels.
+    width: i32,
+    /// The number of bytes per row in the glyph.
+    pitch: i32,
+    /// The offset in pixels of the glyph from the baseline.
+    y_min: i64,
+    /// The offset in pixels of the left edge of the glyph from the pen position.
+    x_min: i64,
+    /// The distance in pixels the pen advances after the glyph.
+    advance: i64,
+    /// The horizontal position in pixels of the pen in vertical text, from the pen position
+    /// in horizontal text.
+    vertical_origin_x: i64,
+    /// The vertical position in pixels of the pen in vertical text, up from the baseline.
+    vertical_origin_y: i64,
+    /// The distance in pixels the pen advances down after the glyph in vertical text.
+    vertical_advance: i64,
+    /// The bitmap image of the glyph.
+    image: GlyphImage,
+}
 
-    // Tell FreeType the maximum size of each glyph, in pixels.
-    // The glyph height in pixels.
-    let mut glyph_rows = vec![0 as i32; 256];
-    // The glyph width in pixels.
-    let mut glyph_width = vec![0 as i32; 256];
-    // The bytes to per row of pixels per glyph.
-    let mut glyph_pitch = vec![0 as i32; 256];
-    // The offset for letters that dip below the baseline like 'g' and 'y', for example.
-    let mut glyph_ymin = vec![0 as i64; 256];
-    // A table for storing the sampled glyph images.
-    let mut glyph_buffer = HashMap::new();
+/// Sample the glyph image and parameters for each code point in a list of code points
+/// from a single typeface. Glyphs keyed by glyph index get loaded by glyph index.
+fn sample_glyphs(
+    face: &freetype::face::Face,
+    spec: AtlasSpec, rendering: GlyphRendering, strike: Option<strikes::Strike>,
+    code_points: &[usize]) -> Result<Vec<GlyphSample>, SampleTypefaceError> {
 
-    // Set the height in pixels width 0 height 48 (48x48).
-    face.set_pixel_sizes(0, spec.glyph_size as u32).map_err(|e| {
-        SampleTypefaceError::SetPixelSize(e, 0, spec.glyph_size)
-    })?;
+    match strike {
+        // Load the hand drawn bitmaps of the strike as they are.
+        Some(strike) => strikes::select_strike(face, strike).map_err(|e| {
+            SampleTypefaceError::SelectStrike(e, strike.pixel_size)
+        })?,
+        // Tell FreeType the maximum size of each glyph, in pixels.
+        // Set the height in pixels width 0 height 48 (48x48).
+        None => face.set_pixel_sizes(0, spec.glyph_size as u32).map_err(|e| {
+            SampleTypefaceError::SetPixelSize(e, 0, spec.glyph_size)
+        })?,
+    }
 
-    for i in 33..256 {
-        face.load_char(i, freetype::face::LoadFlag::RENDER).map_err(|e| {
-            SampleTypefaceError::LoadCharacter(e, i)
-        })?;
+    // Monochrome glyphs are hinted for the monochrome rasterizer as well.
+    let (load_flags, render_mode) = match rendering {
+        GlyphRendering::Mono => (
+            freetype::face::LoadFlag::RENDER | freetype::face::LoadFlag::TARGET_MONO,
+            freetype::render_mode::RenderMode::Mono
+        ),
+        GlyphRendering::Color => (
+            freetype::face::LoadFlag::RENDER | freetype::face::LoadFlag::COLOR,
+            freetype::render_mode::RenderMode::Normal
+        ),
+        _ => (freetype::face::LoadFlag::RENDER, freetype::render_mode::RenderMode::Normal),
+    };
 
-        // Draw a glyph image anti-aliased.
+    let mut samples = Vec::with_capacity(code_points.len());
+    for &i in code_points.iter() {
+        let loaded = if is_glyph_index_key(i) {
+            face.load_glyph((i - GLYPH_INDEX_KEY_BASE) as u32, load_flags)
+        } else {
+            face.load_char(i, load_flags)
+        };
+        loaded.map_err(|e| SampleTypefaceError::LoadCharacter(e, i))?;
+
+        // Draw a glyph image anti-aliased, or in monochrome.
         let glyph_handle = face.glyph();
 
-        glyph_handle.render_glyph(freetype::render_mode::RenderMode::Normal).map_err(|e| {
+        glyph_handle.render_glyph(render_mode).map_err(|e| {
             SampleTypefaceError::RenderCharacter(e, i)
         })?;
 
-        // Get the dimensions of the bitmap.
-        glyph_rows[i] = glyph_handle.bitmap().rows();
-        glyph_width[i] = glyph_handle.bitmap().width();
-        glyph_pitch[i] = glyph_handle.bitmap().pitch();
-
-        let glyph_image_i = create_glyph_image(glyph_handle);
-        glyph_buffer.insert(i, glyph_image_i);
-
         // Get the y-offset to place glyphs on baseline. This data lies in the bounding box.
         let glyph = match glyph_handle.get_glyph() {
             Ok(val) => val,
@@ -204,7 +465,185 @@ fn sample_typeface(
         // Get the bounding box. Here "truncated" mode specifies that the dimensions
         // of the bounding box are given in pixels.
         let bbox = glyph.get_cbox(freetype::ffi::FT_GLYPH_BBOX_TRUNCATE);
-        glyph_ymin[i] = bbox.yMin;
+
+        // Monochrome rendering and bitmap fonts give packed glyph images.
+        let bitmap = glyph_handle.bitmap();
+        let (pitch, mut image) = match bitmap.pixel_mode() {
+            Ok(PixelMode::Mono) => (bitmap.width(), create_packed_glyph_image(glyph_handle, 1)),
+            Ok(PixelMode::Gray2) => (bitmap.width(), create_packed_glyph_image(glyph_handle, 2)),
+            Ok(PixelMode::Gray4) => (bitmap.width(), create_packed_glyph_image(glyph_handle, 4)),
+            Ok(PixelMode::Bgra) => (bitmap.width(), create_color_glyph_image(glyph_handle)),
+            _ => (bitmap.pitch(), create_glyph_image(glyph_handle)),
+        };
+        if let GlyphRendering::Threshold(threshold) = rendering {
+            for value in image.data.iter_mut() {
+                *value = if *value >= threshold { 255 } else { 0 };
+            }
+        }
+
+        // The vertical metrics are in 26.6 fixed point. Fonts made for vertical text have them
+        // in their `vhea` and `vmtx` tables. Other fonts get them synthesized from the line
+        // height, with the glyph centered on the pen horizontally, and centered in an advance
+        // of one line height vertically.
+        let metrics = glyph_handle.metrics();
+        let (vertical_bearing_x, vertical_bearing_y, vertical_advance) = if face.has_vertical() {
+            (metrics.vertBearingX, metrics.vertBearingY, metrics.vertAdvance)
+        } else {
+            let line_height = face.size_metrics().map_or(metrics.height, |size_metrics| size_metrics.height);
+            (metrics.horiBearingX - metrics.horiAdvance / 2, (line_height - metrics.height) / 2, line_height)
+        };
+
+        let sample = GlyphSample {
+            code_point: i,
+            // The glyph height in pixels.
+            rows: glyph_handle.bitmap().rows(),
+            // The glyph width in pixels.
+            width: glyph_handle.bitmap().width(),
+            // The bytes to per row of pixels per glyph.
+            pitch: pitch,
+            // The offset for letters that dip below the baseline like 'g' and 'y', for example.
+            y_min: bbox.yMin,
+            // The offset for glyphs that start to the left or right of the pen position.
+            x_min: bbox.xMin,
+            // The advance is in 26.6 fixed point.
+            advance: glyph_handle.advance().x >> 6,
+            // The bearings place the top left corner of the glyph relative to the pen in
+            // vertical text, with the y axis pointing down.
+            vertical_origin_x: (metrics.horiBearingX - vertical_bearing_x + 32) >> 6,
+            vertical_origin_y: (metrics.horiBearingY + vertical_bearing_y + 32) >> 6,
+            vertical_advance: vertical_advance >> 6,
+            image: image,
+        };
+        match strike {
+            Some(strike) if sample.image.color.is_some() && strike.pixel_size != spec.glyph_size => {
+                samples.push(scale_color_glyph(sample, spec.glyph_size as f64 / strike.pixel_size as f64));
+            }
+            _ => samples.push(sample),
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Sample the glyphs for a list of code points on a separate thread. FreeType library
+/// and face handles cannot be shared between threads, so each worker opens its own.
+fn spawn_sampling_worker(
+    font_path: PathBuf,
+    spec: AtlasSpec,
+    rendering: GlyphRendering,
+    strike: Option<strikes::Strike>,
+    code_points: Vec<usize>) -> thread::JoinHandle<Result<Vec<GlyphSample>, SampleTypefaceError>> {
+
+    thread::spawn(move || {
+        let ft = Library::init().map_err(SampleTypefaceError::InitLibrary)?;
+        let face = ft.new_face(&font_path, 0).map_err(SampleTypefaceError::OpenFace)?;
+
+        sample_glyphs(&face, spec, rendering, strike, &code_points)
+    })
+}
+
+/// Generate the glyph image for each individual glyph slot in the typeface to be
+/// mapped into the final atlas image. With more than one job, the code points are split
+/// into contiguous chunks that are rasterized in parallel, each chunk by a worker with its
+/// own FreeType face opened from `font_path`. The chunks are merged back in code point
+/// order, so the glyph table is the same no matter how many jobs are used. Besides the
+/// printable code points, the glyphs with the keys in `glyph_index_keys` get sampled.
+fn sample_typeface(
+    face: &freetype::face::Face,
+    font_path: &Path, spec: AtlasSpec, rendering: GlyphRendering, strike: Option<strikes::Strike>, jobs: usize,
+    glyph_index_keys: &[usize],
+    glyph_cache: Option<&cache::GlyphCache>) -> Result<GlyphTable, SampleTypefaceError> {
+
+    // The glyph height in pixels.
+    let mut glyph_rows = HashMap::new();
+    // The glyph width in pixels.
+    let mut glyph_width = HashMap::new();
+    // The bytes to per row of pixels per glyph.
+    let mut glyph_pitch = HashMap::new();
+    // The offset for letters that dip below the baseline like 'g' and 'y', for example.
+    let mut glyph_ymin = HashMap::new();
+    // The offset for letters that start away from the pen position.
+    let mut glyph_xmin = HashMap::new();
+    // The distance the pen moves after each glyph.
+    let mut glyph_advance = HashMap::new();
+    // The position of the pen in vertical text, and the distance it moves down after each glyph.
+    let mut glyph_vertical_origin_x = HashMap::new();
+    let mut glyph_vertical_origin_y = HashMap::new();
+    let mut glyph_vertical_advance = HashMap::new();
+    // The glyph indices are looked up again every time, so the glyph cache does not hold them.
+    let mut glyph_index = HashMap::new();
+    let mut mapped_code_point = HashMap::new();
+    let code_points_by_glyph = if glyph_index_keys.is_empty() {
+        HashMap::new()
+    } else {
+        glyph_ids::code_points_by_glyph(face)
+    };
+    // A table for storing the sampled glyph images.
+    let mut glyph_buffer = BTreeMap::new();
+
+    // Only render the glyphs that are missing from the glyph cache.
+    let mut cached_samples = vec![];
+    let mut code_points = vec![];
+    for i in (32..256).chain(glyph_index_keys.iter().cloned()) {
+        if is_glyph_index_key(i) {
+            let index = (i - GLYPH_INDEX_KEY_BASE) as u32;
+            glyph_index.insert(i, index);
+            if let Some(&code_point) = code_points_by_glyph.get(&index) {
+                mapped_code_point.insert(i, code_point);
+            }
+        } else {
+            glyph_index.insert(i, face.get_char_index(i));
+        }
+        // The space has no image.
+        if i == 32 {
+            continue;
+        }
+        match glyph_cache.and_then(|glyph_cache| glyph_cache.load(i)) {
+            Some(sample) => cached_samples.push(sample),
+            None => code_points.push(i),
+        }
+    }
+
+    let samples = if jobs <= 1 || code_points.len() <= 1 {
+        sample_glyphs(face, spec, rendering, strike, &code_points)?
+    } else {
+        let chunk_size = (code_points.len() + jobs - 1) / jobs;
+        let workers: Vec<_> = code_points.chunks(chunk_size).map(|chunk| {
+            spawn_sampling_worker(font_path.to_path_buf(), spec, rendering, strike, chunk.to_vec())
+        }).collect();
+
+        let mut samples = Vec::with_capacity(code_points.len());
+        for worker in workers {
+            let chunk_samples = match worker.join() {
+                Ok(val) => val?,
+                Err(e) => panic::resume_unwind(e),
+            };
+            samples.extend(chunk_samples);
+        }
+
+        samples
+    };
+
+    if let Some(glyph_cache) = glyph_cache {
+        for sample in samples.iter() {
+            // A glyph that fails to make it into the cache gets rendered again next time,
+            // so there is no reason to fail the whole run over it.
+            let _ = glyph_cache.store(sample);
+        }
+    }
+
+    for sample in cached_samples.into_iter().chain(samples.into_iter()) {
+        let i = sample.code_point;
+        glyph_rows.insert(i, sample.rows);
+        glyph_width.insert(i, sample.width);
+        glyph_pitch.insert(i, sample.pitch);
+        glyph_ymin.insert(i, sample.y_min);
+        glyph_xmin.insert(i, sample.x_min);
+        glyph_advance.insert(i, sample.advance);
+        glyph_vertical_origin_x.insert(i, sample.vertical_origin_x);
+        glyph_vertical_origin_y.insert(i, sample.vertical_origin_y);
+        glyph_vertical_advance.insert(i, sample.vertical_advance);
+        glyph_buffer.insert(i, sample.image);
     }
 
     Ok(GlyphTable {
@@ -212,99 +651,110 @@ fn sample_typeface(
         width: glyph_width,
         pitch: glyph_pitch,
         y_min: glyph_ymin,
+        x_min: glyph_xmin,
+        advance: glyph_advance,
+        vertical_origin_x: glyph_vertical_origin_x,
+        vertical_origin_y: glyph_vertical_origin_y,
+        vertical_advance: glyph_vertical_advance,
+        glyph_index: glyph_index,
+        mapped_code_point: mapped_code_point,
         buffer: glyph_buffer,
     })
 }
 
+/// The offset in pixels of the bottom edge of the placed part of a glyph image from the
+/// baseline.
+fn placed_glyph_bottom(glyph_tab: &GlyphTable, placement: &layout::GlyphPlacement) -> i64 {
+    let rows = glyph_tab.rows[&placement.code_point] as i64;
+    let bounds = placement.bounds;
+
+    glyph_tab.y_min[&placement.code_point] + rows - (bounds.y + bounds.height) as i64
+}
+
 /// Calculate the metadata for indexing into the atlas bitmap image.
-fn create_bitmap_metadata(glyph_tab: &GlyphTable, spec: AtlasSpec) -> HashMap<usize, GlyphMetadata> {
+fn create_bitmap_metadata(glyph_tab: &GlyphTable, layout: &layout::AtlasLayout) -> HashMap<usize, GlyphMetadata> {
+    let spec = layout.spec;
     let mut metadata = HashMap::new();
-    let glyph_metadata_space = GlyphMetadata::new(32, 0, 0, 0.5, 1.0, 0.0, 0.0, 0.0);
+    // The space has no image. In a grid the slot for it is empty, but in a trimmed atlas
+    // the top left corner holds another glyph, so the space covers no rows there.
+    let space_height = if layout.trimmed { 0.0 } else { 1.0 };
+    let glyph_metadata_space = GlyphMetadata::new(32, 0, 0, 0.5, space_height, 0.0, 0.0, 0.0);
     metadata.insert(32, glyph_metadata_space);
-    for i in glyph_tab.buffer.keys() {
-        let order = i - 32;
-        let col = order % spec.columns;
-        let row = order % spec.columns;
+    for placement in layout.placements.iter() {
+        let i = placement.code_point;
+        let bottom = placed_glyph_bottom(glyph_tab, placement);
 
         // Glyph metadata parameters.
-        let x_min = (col * spec.slot_glyph_size) as f32 / spec.width as f32;
-        let y_min = (row * spec.slot_glyph_size) as f32 / spec.height as f32;
-        let width = (glyph_tab.width[*i] + spec.padding as i32) as f32 / spec.slot_glyph_size as f32;
-        let height = (glyph_tab.rows[*i] + spec.padding as i32) as f32 / spec.slot_glyph_size as f32;
-        let y_offset = -(spec.padding as f32 - glyph_tab.y_min[*i] as f32) / spec.slot_glyph_size as f32;
+        let x_min = placement.x as f32 / spec.width as f32;
+        let y_min = placement.y as f32 / spec.height as f32;
+        let width = (placement.bounds.width + spec.padding) as f32 / spec.slot_glyph_size as f32;
+        let height = (placement.bounds.height + spec.padding) as f32 / spec.slot_glyph_size as f32;
+        let y_offset = -(spec.padding as f32 - bottom as f32) / spec.slot_glyph_size as f32;
 
-        let row = order / spec.rows;
-        let column = order % spec.columns;
-        let glyph_metadata_i = GlyphMetadata::new(*i, row, column, width, height, x_min, y_min, y_offset);
-        metadata.insert(*i, glyph_metadata_i);
+        let glyph_metadata_i = GlyphMetadata::new(
+            i, placement.row, placement.column, width, height, x_min, y_min, y_offset
+        );
+        metadata.insert(i, glyph_metadata_i);
     }
 
     metadata
 }
 
 /// Pack the glyph bitmap images sampled from the typeface into a single bitmap image.
-fn create_bitmap_image(glyph_tab: &GlyphTable, spec: AtlasSpec) -> bmfa::BitmapFontAtlasImage {
-    // Next we can open a file stream to write our atlas image to.
+fn create_bitmap_image(glyph_tab: &GlyphTable, layout: &layout::AtlasLayout) -> bmfa::BitmapFontAtlasImage {
+    let spec = layout.spec;
+    // Everything outside of the glyph images is a transparent black pixel (0,0,0,0).
     let mut atlas_buffer = vec![
         0 as u8; spec.width * spec.height * 4 * mem::size_of::<u8>()
     ];
-    let mut atlas_buffer_index = 0;
-    for y in 0..spec.height {
-        for x in 0..spec.width {
-            // Work out which grid slot (col, row) we are in i.e. out of 16 glyphs x 16 glyphs.
-            let col = x / spec.slot_glyph_size;
-            let row = y / spec.slot_glyph_size;
-            let order = row * spec.columns + col;
-            let glyph_index = order + 32;
-
-            if (glyph_index > 32) && (glyph_index < 256) {
-                // A glyph exists for this code point in the bitmap.
-                // Pixel indices within padded glyph slot area.
-                let x_loc = ((x % spec.slot_glyph_size) as i32) - ((spec.padding / 2) as i32);
-                let y_loc = ((y % spec.slot_glyph_size) as i32) - ((spec.padding / 2) as i32);
-                // Outside of the glyph dimensions we use as default value a
-                // transparent black pixel (0,0,0,0).
-                if x_loc < 0 || y_loc < 0 || x_loc >= glyph_tab.width[glyph_index] ||
-                    y_loc >= glyph_tab.rows[glyph_index] {
-                    atlas_buffer[atlas_buffer_index] = 0;
-                    atlas_buffer_index += 1;
-                    atlas_buffer[atlas_buffer_index] = 0;
-                    atlas_buffer_index += 1;
-                    atlas_buffer[atlas_buffer_index] = 0;
-                    atlas_buffer_index += 1;
-                    atlas_buffer[atlas_buffer_index] = 0;
-                    atlas_buffer_index += 1;
-                } else {
-                    // this is 1, but it's safer to put it in anyway
-                    // int bytes_per_pixel = gwidth[glyph_index] / gpitch[glyph_index];
-                    // int bytes_in_glyph = grows[glyph_index] * gpitch[glyph_index];
-                    let byte_order_in_glyph = y_loc * glyph_tab.width[glyph_index] + x_loc;
-                    let mut colour = [0 as u8; 4];
-                    colour[0] = glyph_tab.buffer[&glyph_index].data[byte_order_in_glyph as usize];
-                    colour[1] = colour[0];
-                    colour[2] = colour[0];
-                    colour[3] = colour[0];
-
-                    atlas_buffer[atlas_buffer_index] = glyph_tab.buffer[&glyph_index].data[byte_order_in_glyph as usize];
-                    atlas_buffer_index += 1;
-                    atlas_buffer[atlas_buffer_index] = glyph_tab.buffer[&glyph_index].data[byte_order_in_glyph as usize];
-                    atlas_buffer_index += 1;
-                    atlas_buffer[atlas_buffer_index] = glyph_tab.buffer[&glyph_index].data[byte_order_in_glyph as usize];
-                    atlas_buffer_index += 1;
-                    atlas_buffer[atlas_buffer_index] = glyph_tab.buffer[&glyph_index].data[byte_order_in_glyph as usize];
-                    atlas_buffer_index += 1;
+    let half_padding = spec.padding / 2;
+    for placement in layout.placements.iter() {
+        if placement.shares_image_with.is_some() {
+            // The image is already in the atlas.
+            continue;
+        }
+        let i = placement.code_point;
+        let pitch = glyph_tab.pitch[&i].abs() as usize;
+        let glyph_data = &glyph_tab.buffer[&i].data;
+        let color_data = glyph_tab.buffer[&i].color.as_ref();
+        let bounds = placement.bounds;
+        for y_loc in 0..bounds.height {
+            for x_loc in 0..bounds.width {
+                // Pixels past the glyph slot or the edge of the atlas are clipped.
+                if half_padding + x_loc >= placement.cell_width
+                    || half_padding + y_loc >= placement.cell_height {
+                    continue;
                 }
-            } else {
-                // A glyph does not exist for this code point in the bitmap. We choose to use a
-                // a transparent black pixel value (0,0,0,0).
-                atlas_buffer[atlas_buffer_index] = 0;
-                atlas_buffer_index += 1;
-                atlas_buffer[atlas_buffer_index] = 0;
-                atlas_buffer_index += 1;
-                atlas_buffer[atlas_buffer_index] = 0;
-                atlas_buffer_index += 1;
-                atlas_buffer[atlas_buffer_index] = 0;
-                atlas_buffer_index += 1;
+                let x = placement.x + half_padding + x_loc;
+                let y = placement.y + half_padding + y_loc;
+                if x >= spec.width || y >= spec.height {
+                    continue;
+                }
+
+                let glyph_index = (bounds.y + y_loc) * pitch + bounds.x + x_loc;
+                let atlas_buffer_index = 4 * (y * spec.width + x);
+                match color_data {
+                    // Color glyphs keep their colors.
+                    Some(color_data) => {
+                        atlas_buffer[atlas_buffer_index..(atlas_buffer_index + 4)]
+                            .copy_from_slice(&color_data[(4 * glyph_index)..(4 * glyph_index + 4)]);
+                    }
+                    None => {
+                        let value = glyph_data[glyph_index];
+                        atlas_buffer[atlas_buffer_index] = value;
+                        atlas_buffer[atlas_buffer_index + 1] = value;
+                        atlas_buffer[atlas_buffer_index + 2] = value;
+                        atlas_buffer[atlas_buffer_index + 3] = value;
+                    }
+                }
+            }
+        }
+    }
+
+    if spec.extrude > 0 {
+        for placement in layout.placements.iter() {
+            if placement.shares_image_with.is_none() {
+                extrude_glyph(&mut atlas_buffer, spec, placement);
             }
         }
     }
@@ -312,16 +762,7 @@ fn create_bitmap_image(glyph_tab: &GlyphTable, spec: AtlasSpec) -> bmfa::BitmapF
     if spec.origin == bmfa::Origin::BottomLeft {
         // If the origin is the bottom left of the image, we need to flip the image back over
         // before writing it out.
-        let height = spec.height;
-        let width_in_bytes = 4 * spec.width;
-        let half_height = height / 2;
-        for row in 0..half_height {
-            for col in 0..width_in_bytes {
-                let temp = atlas_buffer[row * width_in_bytes + col];
-                atlas_buffer[row * width_in_bytes + col] = atlas_buffer[((height - row - 1) * width_in_bytes) + col];
-                atlas_buffer[((height - row - 1) * width_in_bytes) + col] = temp;
-            }
-        }
+        flip_vertically(&mut atlas_buffer, 4 * spec.width, spec.height);
     }
 
     bmfa::BitmapFontAtlasImage::new(
@@ -329,45 +770,500 @@ fn create_bitmap_image(glyph_tab: &GlyphTable, spec: AtlasSpec) -> bmfa::BitmapF
     )
 }
 
-/// Create a bitmapped atlas from a vector based font atlas.
-fn create_bitmap_atlas(
-    face: freetype::face::Face, spec: AtlasSpec) -> Result<BitmapFontAtlas, SampleTypefaceError> {
+/// Copy the pixels along the edges of a glyph rectangle outward by `spec.extrude` pixels
+/// on every side, so that a texture filter reaching past the edge of the rectangle picks up
+/// the edge of the glyph instead of whatever sits next to it. The buffer is in the top left
+/// coordinate chart.
+fn extrude_glyph(atlas_buffer: &mut [u8], spec: AtlasSpec, placement: &layout::GlyphPlacement) {
+    let width = usize::min(placement.bounds.width + spec.padding, placement.cell_width) as i64;
+    let height = usize::min(placement.bounds.height + spec.padding, placement.cell_height) as i64;
+    if width == 0 || height == 0 {
+        return;
+    }
 
-    let glyph_tab = match sample_typeface(face, spec) {
-        Ok(val) => val,
-        Err(e) => return Err(e),
-    };
-    let glyph_metadata = create_bitmap_metadata(&glyph_tab, spec);
-    let atlas_image = create_bitmap_image(&glyph_tab, spec);
+    let extrude = spec.extrude as i64;
+    for dy in -extrude..(height + extrude) {
+        for dx in -extrude..(width + extrude) {
+            if dx >= 0 && dx < width && dy >= 0 && dy < height {
+                continue;
+            }
+            let x = placement.x as i64 + dx;
+            let y = placement.y as i64 + dy;
+            if x < 0 || y < 0 || x >= spec.width as i64 || y >= spec.height as i64 {
+                continue;
+            }
+
+            // Clamp to the nearest pixel inside the rectangle.
+            let src_x = placement.x as i64 + i64::min(i64::max(dx, 0), width - 1);
+            let src_y = placement.y as i64 + i64::min(i64::max(dy, 0), height - 1);
+            let src = 4 * (src_y as usize * spec.width + src_x as usize);
+            let dst = 4 * (y as usize * spec.width + x as usize);
+            for channel in 0..4 {
+                atlas_buffer[dst + channel] = atlas_buffer[src + channel];
+            }
+        }
+    }
+}
+
+/// Flip an image buffer upside down in place. This converts an image between the top left
+/// and bottom left coordinate charts.
+fn flip_vertically(buffer: &mut [u8], width_in_bytes: usize, height: usize) {
+    let half_height = height / 2;
+    for row in 0..half_height {
+        for col in 0..width_in_bytes {
+            let temp = buffer[row * width_in_bytes + col];
+            buffer[row * width_in_bytes + col] = buffer[((height - row - 1) * width_in_bytes) + col];
+            buffer[((height - row - 1) * width_in_bytes) + col] = temp;
+        }
+    }
+}
+
+/// Create a bitmapped atlas from the glyphs sampled from a vector based font.
+fn create_bitmap_atlas(glyph_tab: &GlyphTable, layout: &layout::AtlasLayout) -> BitmapFontAtlas {
+    let spec = layout.spec;
+    let glyph_metadata = create_bitmap_metadata(glyph_tab, layout);
+    let atlas_image = create_bitmap_image(glyph_tab, layout);
 
     let metadata = BitmapFontAtlasMetadata {
         origin: spec.origin,
         width: spec.width,
         height: spec.height,
         columns: spec.columns,
-        rows: spec.columns,
+        rows: spec.rows,
         padding: spec.padding,
         slot_glyph_size: spec.slot_glyph_size,
         glyph_size: spec.glyph_size,
         glyph_metadata: glyph_metadata,
     };
 
-    Ok(BitmapFontAtlas::new(metadata, atlas_image))
+    BitmapFontAtlas::new(metadata, atlas_image)
+}
+
+/// A copy of the atlas metadata with the glyphs ordered by code point. The glyph metadata
+/// in a `BitmapFontAtlasMetadata` lives in a hash map, whose iteration order changes from
+/// run to run, so any metadata `fontgen` writes out itself goes through this type to keep
+/// the output the same every time.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct OrderedAtlasMetadata {
+    origin: bmfa::Origin,
+    width: usize,
+    height: usize,
+    columns: usize,
+    rows: usize,
+    padding: usize,
+    slot_glyph_size: usize,
+    glyph_size: usize,
+    glyph_metadata: BTreeMap<usize, GlyphMetadata>,
+}
+
+impl<'a> From<&'a BitmapFontAtlasMetadata> for OrderedAtlasMetadata {
+    fn from(metadata: &'a BitmapFontAtlasMetadata) -> OrderedAtlasMetadata {
+        OrderedAtlasMetadata {
+            origin: metadata.origin,
+            width: metadata.width,
+            height: metadata.height,
+            columns: metadata.columns,
+            rows: metadata.rows,
+            padding: metadata.padding,
+            slot_glyph_size: metadata.slot_glyph_size,
+            glyph_size: metadata.glyph_size,
+            glyph_metadata: metadata.glyph_metadata.iter()
+                .map(|(code_point, glyph)| (*code_point, *glyph))
+                .collect(),
+        }
+    }
+}
+
+impl From<OrderedAtlasMetadata> for BitmapFontAtlasMetadata {
+    fn from(metadata: OrderedAtlasMetadata) -> BitmapFontAtlasMetadata {
+        BitmapFontAtlasMetadata {
+            origin: metadata.origin,
+            width: metadata.width,
+            height: metadata.height,
+            columns: metadata.columns,
+            rows: metadata.rows,
+            padding: metadata.padding,
+            slot_glyph_size: metadata.slot_glyph_size,
+            glyph_size: metadata.glyph_size,
+            glyph_metadata: metadata.glyph_metadata.into_iter().collect(),
+        }
+    }
+}
+
+/// The exact pixel metrics of a single glyph in an atlas.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+struct GlyphMetrics {
+    /// The horizontal position of the left edge of the glyph rectangle from the left
+    /// of the atlas image, in pixels.
+    x: usize,
+    /// The vertical position of the top edge of the glyph rectangle from the top of the
+    /// atlas image, in pixels, whatever the origin of the atlas is.
+    y: usize,
+    /// The width of the glyph rectangle in pixels, including padding.
+    width: usize,
+    /// The height of the glyph rectangle in pixels, including padding.
+    height: usize,
+    /// The offset in pixels from the pen position to the left edge of the glyph rectangle.
+    x_offset: i64,
+    /// The offset in pixels from the baseline up to the top edge of the glyph rectangle.
+    y_offset: i64,
+    /// The distance in pixels the pen advances after the glyph.
+    advance: i64,
+    /// The offset in pixels from the pen position in vertical text to the left edge of the
+    /// glyph rectangle.
+    #[serde(default)]
+    vertical_x_offset: i64,
+    /// The offset in pixels from the pen position in vertical text up to the top edge of the
+    /// glyph rectangle. The pen sits above the glyph, so this is usually negative.
+    #[serde(default)]
+    vertical_y_offset: i64,
+    /// The distance in pixels the pen advances down after the glyph in vertical text.
+    #[serde(default)]
+    vertical_advance: i64,
+    /// Whether the glyph is drawn in its own colors, so it must not be tinted with the color
+    /// of the text. Only color glyphs record this.
+    #[serde(default, skip_serializing_if = "is_false")]
+    color: bool,
+    /// The index of the glyph in the font.
+    #[serde(default)]
+    glyph_id: u32,
+    /// The code point the character map of the font maps to a glyph keyed by glyph index,
+    /// if it maps any. Glyphs keyed by code point leave this out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mapped_code_point: Option<usize>,
+}
+
+/// Whether a flag is off, so that serializing can leave it out.
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// The pixel metrics of every glyph in an atlas whose glyphs were trimmed, or that has a gutter
+/// between glyphs. The atlas metadata only places glyphs vertically and has no room for the
+/// gutter, so this gets written next to the atlas file to record where each glyph sits
+/// relative to the pen position, and how the glyphs are separated.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct AtlasMetrics {
+    /// The width of the atlas in pixels.
+    width: usize,
+    /// The height of the atlas in pixels.
+    height: usize,
+    /// The amount of padding around each glyph in pixels.
+    padding: usize,
+    /// The number of pixels around each glyph rectangle filled with copies of its edge pixels.
+    /// The glyph rectangles do not include them.
+    extrude: usize,
+    /// The number of empty pixels between neighbouring glyphs, outside of the extrusion.
+    spacing: usize,
+    /// The metrics of each glyph, keyed by code point.
+    glyphs: BTreeMap<usize, GlyphMetrics>,
+}
+
+impl AtlasMetrics {
+    /// Load the metrics from a JSON file.
+    fn load(path: &Path) -> Result<AtlasMetrics, Box<dyn std::error::Error>> {
+        let file = File::open(path)?;
+        let metrics = serde_json::from_reader(file)?;
+
+        Ok(metrics)
+    }
+
+    /// Write the metrics out to a JSON file.
+    fn write_to_file(&self, path: &Path) -> Result<(), AppError> {
+        let file = match File::create(path) {
+            Ok(val) => val,
+            Err(_) => return Err(AppError::CouldNotCreateAtlasFile(path.to_path_buf())),
+        };
+        if serde_json::to_writer_pretty(file, self).is_err() {
+            return Err(AppError::CouldNotCreateAtlasFile(path.to_path_buf()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Compute the pixel metrics for each glyph placed in an atlas.
+fn create_atlas_metrics(glyph_tab: &GlyphTable, layout: &layout::AtlasLayout) -> AtlasMetrics {
+    let spec = layout.spec;
+    let half_padding = (spec.padding / 2) as i64;
+    let mut glyphs = BTreeMap::new();
+    // The space advances the pen by half a slot, the same as its width in the atlas metadata.
+    glyphs.insert(32, GlyphMetrics {
+        x: 0,
+        y: 0,
+        width: 0,
+        height: 0,
+        x_offset: 0,
+        y_offset: 0,
+        advance: (spec.slot_glyph_size / 2) as i64,
+        vertical_x_offset: 0,
+        vertical_y_offset: 0,
+        // In vertical text, the space advances the pen by a whole slot.
+        vertical_advance: spec.slot_glyph_size as i64,
+        color: false,
+        glyph_id: glyph_tab.glyph_index.get(&32).cloned().unwrap_or(0),
+        mapped_code_point: None,
+    });
+    for placement in layout.placements.iter() {
+        let i = placement.code_point;
+        let bounds = placement.bounds;
+        let top = placed_glyph_bottom(glyph_tab, placement) + bounds.height as i64;
+        let x_offset = glyph_tab.x_min[&i] + bounds.x as i64 - half_padding;
+        let y_offset = top + half_padding;
+        glyphs.insert(i, GlyphMetrics {
+            x: placement.x,
+            y: placement.y,
+            width: bounds.width + spec.padding,
+            height: bounds.height + spec.padding,
+            x_offset: x_offset,
+            y_offset: y_offset,
+            advance: glyph_tab.advance[&i],
+            vertical_x_offset: x_offset - glyph_tab.vertical_origin_x[&i],
+            vertical_y_offset: y_offset - glyph_tab.vertical_origin_y[&i],
+            vertical_advance: glyph_tab.vertical_advance[&i],
+            color: glyph_tab.buffer[&i].color.is_some(),
+            glyph_id: glyph_tab.glyph_index[&i],
+            mapped_code_point: glyph_tab.mapped_code_point.get(&i).cloned(),
+        });
+    }
+
+    AtlasMetrics {
+        width: spec.width,
+        height: spec.height,
+        padding: spec.padding,
+        extrude: spec.extrude,
+        spacing: spec.spacing,
+        glyphs: glyphs,
+    }
+}
+
+/// The path to the metrics file written next to an atlas file.
+fn atlas_metrics_path(atlas_path: &Path) -> PathBuf {
+    atlas_path.with_extension("metrics.json")
+}
+
+/// The path to the substitution table written next to an atlas file.
+fn substitutions_path(atlas_path: &Path) -> PathBuf {
+    atlas_path.with_extension("substitutions.json")
+}
+
+/// The path to the table of glyphs picked by name written next to an atlas file.
+fn glyph_names_path(atlas_path: &Path) -> PathBuf {
+    atlas_path.with_extension("names.json")
+}
+
+/// The path to the packed bitmaps of a monochrome atlas written next to an atlas file.
+fn packed_bitmaps_path(atlas_path: &Path) -> PathBuf {
+    atlas_path.with_extension("packed.bin")
+}
+
+/// The path to the table of the packed bitmaps written next to an atlas file.
+fn packed_table_path(atlas_path: &Path) -> PathBuf {
+    atlas_path.with_extension("packed.json")
+}
+
+/// The path to the texture file written next to an atlas file.
+fn texture_path(atlas_path: &Path, image_format: texture::ImageFormat) -> PathBuf {
+    atlas_path.with_extension(image_format.name())
+}
+
+/// The path to the glyph metadata written next to a texture that cannot hold it.
+fn texture_metadata_path(atlas_path: &Path) -> PathBuf {
+    atlas_path.with_extension("metadata.json")
+}
+
+/// The placement of the atlas for a single glyph size inside a shared atlas texture.
+#[derive(Clone, Serialize)]
+struct SharedAtlasEntry {
+    /// The size of a glyph slot in pixels for this entry.
+    slot_glyph_size: usize,
+    /// The size of a glyph inside the slot in pixels for this entry.
+    glyph_size: usize,
+    /// The horizontal offset of the entry inside the shared texture, in pixels.
+    x: usize,
+    /// The vertical offset of the entry inside the shared texture, in pixels. It is measured from
+    /// the top of the image with a top left origin, and from the bottom of the image with a
+    /// bottom left origin, since that image is written upside down.
+    y: usize,
+    /// The width of the entry in pixels.
+    width: usize,
+    /// The height of the entry in pixels.
+    height: usize,
+    /// The number of glyphs per row in the entry.
+    columns: usize,
+    /// The number of glyphs per column in the entry.
+    rows: usize,
+    /// The glyph metadata for each code point, relative to the shared texture.
+    glyph_metadata: BTreeMap<usize, GlyphMetadata>,
+}
+
+/// The metadata for a shared atlas texture holding the same typeface at several sizes.
+/// Glyphs are keyed by glyph size first and code point second.
+#[derive(Clone, Serialize)]
+struct SharedAtlasMetadata {
+    /// The origin and coordinate chart for the shared atlas image.
+    origin: bmfa::Origin,
+    /// The width of the shared atlas in pixels.
+    width: usize,
+    /// The height of the shared atlas in pixels.
+    height: usize,
+    /// The amount of padding around each glyph in pixels.
+    padding: usize,
+    /// The atlas for each glyph size, in the order the sizes were requested.
+    sizes: Vec<SharedAtlasEntry>,
+}
+
+/// A single texture holding the atlases for several glyph sizes.
+struct SharedAtlas {
+    metadata: SharedAtlasMetadata,
+    image: bmfa::BitmapFontAtlasImage,
+}
+
+/// Pack the atlases for several glyph sizes into one shared texture. The atlases are stacked
+/// away from the origin of the texture in the order they are given. The glyph positions of each
+/// atlas are rescaled to index into the shared texture, while the glyph sizes stay fractions of
+/// the slot glyph size of their entry.
+fn create_shared_atlas(atlases: &[BitmapFontAtlas], origin: bmfa::Origin) -> SharedAtlas {
+    let width = atlases.iter().map(|atlas| atlas.metadata.width).max().unwrap_or(0);
+    let height = atlases.iter().map(|atlas| atlas.metadata.height).sum();
+    let width_in_bytes = 4 * width;
+    let mut shared_buffer = vec![0 as u8; width_in_bytes * height];
+    let mut entries = vec![];
+    let mut y_offset = 0;
+    for atlas in atlases.iter() {
+        let atlas_width_in_bytes = 4 * atlas.metadata.width;
+        let atlas_height = atlas.metadata.height;
+        for row in 0..atlas_height {
+            // Copy the rows out in top left order regardless of the origin of the atlas.
+            let src_row = if origin == bmfa::Origin::BottomLeft {
+                atlas_height - row - 1
+            } else {
+                row
+            };
+            let src = src_row * atlas_width_in_bytes;
+            let dst = (y_offset + row) * width_in_bytes;
+            shared_buffer[dst..(dst + atlas_width_in_bytes)]
+                .copy_from_slice(&atlas.image.data[src..(src + atlas_width_in_bytes)]);
+        }
+
+        let scale_x = atlas.metadata.width as f32 / width as f32;
+        let scale_y = atlas_height as f32 / height as f32;
+        let offset_y = y_offset as f32 / height as f32;
+        let mut glyph_metadata = BTreeMap::new();
+        for (code_point, glyph) in atlas.metadata.glyph_metadata.iter() {
+            let shared_glyph = GlyphMetadata::new(
+                glyph.code_point, glyph.row, glyph.column,
+                glyph.width, glyph.height,
+                glyph.x_min * scale_x, offset_y + glyph.y_min * scale_y,
+                glyph.y_offset
+            );
+            glyph_metadata.insert(*code_point, shared_glyph);
+        }
+
+        entries.push(SharedAtlasEntry {
+            slot_glyph_size: atlas.metadata.slot_glyph_size,
+            glyph_size: atlas.metadata.glyph_size,
+            x: 0,
+            y: y_offset,
+            width: atlas.metadata.width,
+            height: atlas_height,
+            columns: atlas.metadata.columns,
+            rows: atlas.metadata.rows,
+            glyph_metadata: glyph_metadata,
+        });
+        y_offset += atlas_height;
+    }
+
+    if origin == bmfa::Origin::BottomLeft {
+        flip_vertically(&mut shared_buffer, width_in_bytes, height);
+    }
+
+    let padding = atlases.first().map(|atlas| atlas.metadata.padding).unwrap_or(0);
+    let metadata = SharedAtlasMetadata {
+        origin: origin,
+        width: width,
+        height: height,
+        padding: padding,
+        sizes: entries,
+    };
+    let image = bmfa::BitmapFontAtlasImage::new(shared_buffer, width, height, origin);
+
+    SharedAtlas {
+        metadata: metadata,
+        image: image,
+    }
+}
+
+/// Write a shared atlas out as a PNG image and a JSON metadata file.
+fn write_shared_atlas(
+    image_path: &Path, metadata_path: &Path, atlas: &SharedAtlas) -> Result<(), AppError> {
+
+    let image_result = image::save_buffer(
+        image_path, &atlas.image.data,
+        atlas.image.width as u32, atlas.image.height as u32, image::ColorType::RGBA(8)
+    );
+    if image_result.is_err() {
+        return Err(AppError::CouldNotCreateAtlasFile(image_path.to_path_buf()));
+    }
+
+    let metadata_file = match File::create(metadata_path) {
+        Ok(val) => val,
+        Err(_) => return Err(AppError::CouldNotCreateAtlasFile(metadata_path.to_path_buf())),
+    };
+    if serde_json::to_writer_pretty(metadata_file, &atlas.metadata).is_err() {
+        return Err(AppError::CouldNotCreateAtlasFile(metadata_path.to_path_buf()));
+    }
+
+    Ok(())
 }
 
 #[derive(Clone, Debug)]
 enum OptError {
+    InputFileRequired,
+    OutputFileRequired,
     InputFileDoesNotExist(PathBuf),
     InputFileIsNotAFile(PathBuf),
     OutputFileExists(PathBuf),
     SlotGlyphSizeCannotBeZero(usize),
     PaddingLargerThanSlotGlyphSize(usize, usize),
     InvalidOrigin(String),
+    SharedAtlasRequiresSizes,
+    InvalidCodePoint(String),
+    JobsCannotBeZero,
+    PreviewRequiresAtlasFile,
+    SharedAtlasRequiresPlainGrid,
+    DedupeRequiresTrim,
+    TooManyMipLevels(usize, usize),
+    MipmapsRequireImageFormat,
+    InvalidImageFormat(String),
+    ImageFormatRequiresAtlasFile,
+    InvalidExportFormat(String),
+    ExportRequiresAtlasFile,
+    MonoRequiresAtlasFile,
+    InvalidSymbolPrefix(String),
+    InvalidBitOrder(String),
+    InvalidPageLayout(String),
+    MonoOptionRequiresMono,
+    ImageExportRequiresRgbaAtlas(String),
+    ColorConflictsWithMono,
+    ColorRequiresImageExport(String),
+    StrikeLargerThanGlyphSize(usize, usize),
+    InvalidFeatureTag(String),
+    FeaturesRequireTrim,
+    InvalidGlyphIdRange(String),
+    GlyphIdsRequireTrim,
+    MipLevelsOverlap(usize, usize, usize),
 }
 
 impl fmt::Display for OptError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            OptError::InputFileRequired => {
+                write!(f, "An input font file must be given with --input.")
+            }
+            OptError::OutputFileRequired => {
+                write!(f, "An output file must be given with --output.")
+            }
             OptError::InputFileDoesNotExist(ref path) => {
                 write!(f, "The font file {} could not be found.", path.display())
             }
@@ -391,20 +1287,185 @@ impl fmt::Display for OptError {
             OptError::InvalidOrigin(ref origin) => {
                 write!(f, "Selection for image origin invalid. Got {}", origin)
             }
-        }
-    }
-}
-
-impl error::Error for OptError {}
-
-fn parse_origin(st: &str) -> Result<bmfa::Origin, OptError> {
-    match st {
-        "bottom-left" => Ok(bmfa::Origin::BottomLeft),
-        "top-left" => Ok(bmfa::Origin::TopLeft),
-        _ => Err(OptError::InvalidOrigin(format!("{}", st))),
+            OptError::SharedAtlasRequiresSizes => {
+                write!(f, "A shared atlas requires a list of glyph sizes to be given with --sizes.")
+            }
+            OptError::InvalidCodePoint(ref code_point) => {
+                write!(f, "Invalid code point. Got {}", code_point)
+            }
+            OptError::JobsCannotBeZero => {
+                write!(f, "The number of jobs cannot be zero.")
+            }
+            OptError::PreviewRequiresAtlasFile => {
+                write!(
+                    f,
+                    "A preview can only be rendered from an atlas file, which is not written for a shared atlas, \
+                    or for a monochrome atlas without --rgba-atlas."
+                )
+            }
+            OptError::SharedAtlasRequiresPlainGrid => {
+                write!(
+                    f,
+                    "A shared atlas can only hold glyphs in a grid without --trim, --extrude, or --spacing."
+                )
+            }
+            OptError::DedupeRequiresTrim => {
+                write!(f, "Glyph images can only be deduplicated when packing trimmed glyphs with --trim.")
+            }
+            OptError::TooManyMipLevels(levels, width) => {
+                write!(f, "An atlas {} pixels wide cannot have {} mip levels.", width, levels)
+            }
+            OptError::MipmapsRequireImageFormat => {
+                write!(
+                    f,
+                    "Mip levels can only be stored in a texture file, so --mipmaps requires \
+                    --image-format ktx2 or --image-format dds."
+                )
+            }
+            OptError::InvalidImageFormat(ref image_format) => {
+                write!(f, "Selection for image format invalid. Got {}", image_format)
+            }
+            OptError::ImageFormatRequiresAtlasFile => {
+                write!(f, "A texture can only be written for an atlas file, not for a shared atlas.")
+            }
+            OptError::InvalidExportFormat(ref format) => {
+                write!(f, "Selection for export format invalid. Got {}", format)
+            }
+            OptError::ExportRequiresAtlasFile => {
+                write!(f, "An atlas can only be exported with --format for an atlas file, not for a shared atlas.")
+            }
+            OptError::MonoRequiresAtlasFile => {
+                write!(f, "Glyph bitmaps can only be packed with --mono for an atlas file, not for a shared atlas.")
+            }
+            OptError::InvalidSymbolPrefix(ref prefix) => {
+                write!(f, "The symbol prefix must be a C identifier. Got {}", prefix)
+            }
+            OptError::InvalidBitOrder(ref bit_order) => {
+                write!(f, "Selection for bit order invalid. Got {}", bit_order)
+            }
+            OptError::InvalidPageLayout(ref page_layout) => {
+                write!(f, "Selection for page layout invalid. Got {}", page_layout)
+            }
+            OptError::MonoOptionRequiresMono => {
+                write!(f, "The options --threshold, --bit-order, --page-layout, and --rgba-atlas require --mono.")
+            }
+            OptError::ImageExportRequiresRgbaAtlas(ref format) => {
+                write!(
+                    f,
+                    "The {} export describes the atlas image, which is only written for a monochrome \
+                    atlas with --rgba-atlas.",
+                    format
+                )
+            }
+            OptError::ColorConflictsWithMono => {
+                write!(f, "Color glyphs cannot be rendered in monochrome. Use either --color or --mono.")
+            }
+            OptError::ColorRequiresImageExport(ref format) => {
+                write!(
+                    f,
+                    "Color glyphs can only be exported to formats holding the atlas image, not to {}.",
+                    format
+                )
+            }
+            OptError::StrikeLargerThanGlyphSize(strike, glyph_size) => {
+                write!(
+                    f,
+                    "The {} pixel bitmap strike does not fit into the glyph size ({} pixels).",
+                    strike, glyph_size
+                )
+            }
+            OptError::InvalidFeatureTag(ref feature) => {
+                write!(f, "A feature tag must be four printable ASCII characters, like liga. Got {}", feature)
+            }
+            OptError::FeaturesRequireTrim => {
+                write!(
+                    f,
+                    "Glyphs substituted in by --features have no slot in the grid, so they can only \
+                    be packed as trimmed glyphs with --trim."
+                )
+            }
+            OptError::InvalidGlyphIdRange(ref range) => {
+                write!(f, "A glyph ID range must be a glyph index, like 7, or a range of them, like 1-500. Got {}", range)
+            }
+            OptError::GlyphIdsRequireTrim => {
+                write!(
+                    f,
+                    "Glyphs sampled by glyph index with --glyph-ids, --all-glyphs, --glyph-names, or \
+                    --glyph-name-map have no slot in the grid, so they can only be packed as trimmed glyphs \
+                    with --trim."
+                )
+            }
+            OptError::MipLevelsOverlap(level, code_point, other_code_point) => {
+                write!(
+                    f,
+                    "The glyphs for code points {} and {} are too close together to keep apart \
+                    at mip level {}. Increase the spacing between glyphs with --spacing, \
+                    or generate fewer mip levels.",
+                    code_point, other_code_point, level
+                )
+            }
+        }
     }
 }
 
+impl error::Error for OptError {}
+
+fn parse_origin(st: &str) -> Result<bmfa::Origin, OptError> {
+    match st {
+        "bottom-left" => Ok(bmfa::Origin::BottomLeft),
+        "top-left" => Ok(bmfa::Origin::TopLeft),
+        _ => Err(OptError::InvalidOrigin(format!("{}", st))),
+    }
+}
+
+/// The name of an image origin as it is written on the command line.
+fn origin_name(origin: bmfa::Origin) -> &'static str {
+    match origin {
+        bmfa::Origin::BottomLeft => "bottom-left",
+        bmfa::Origin::TopLeft => "top-left",
+    }
+}
+
+/// Parse a code point written either in decimal, or in hexadecimal as `0x41` or `U+0041`.
+fn parse_code_point(st: &str) -> Result<usize, OptError> {
+    let result = if st.starts_with("U+") || st.starts_with("u+") {
+        usize::from_str_radix(&st[2..], 16)
+    } else if st.starts_with("0x") || st.starts_with("0X") {
+        usize::from_str_radix(&st[2..], 16)
+    } else {
+        st.parse::<usize>()
+    };
+
+    result.map_err(|_| OptError::InvalidCodePoint(format!("{}", st)))
+}
+
+/// The subcommands for `fontgen`. When no subcommand is given, `fontgen` generates
+/// an atlas from the input font.
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Print the metadata stored in an existing bitmap font atlas file.
+    #[structopt(name = "inspect")]
+    Inspect(inspect::InspectOpt),
+    /// Extract the image and metadata from an existing bitmap font atlas file into
+    /// a PNG image and a JSON file.
+    #[structopt(name = "unpack")]
+    Unpack(unpack::UnpackOpt),
+    /// Rebuild a bitmap font atlas file from an image and a JSON file written by `unpack`.
+    #[structopt(name = "pack")]
+    Pack(unpack::PackOpt),
+    /// Render a line of sample text into a PNG image using an existing bitmap font atlas file.
+    #[structopt(name = "preview")]
+    Preview(preview::PreviewOpt),
+    /// Watch the input font and glyph name map, and regenerate the atlas every time one of
+    /// them changes. The atlas is generated from the options given before the subcommand,
+    /// and existing output files are overwritten.
+    #[structopt(name = "watch")]
+    Watch(watch::WatchOpt),
+    /// List the pixel sizes of the hand drawn bitmap strikes in a font file.
+    #[structopt(name = "strikes")]
+    Strikes(strikes::StrikesOpt),
+}
+
 /// The shell input options for `fontgen`.
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -415,11 +1476,11 @@ struct Opt {
     /// The path to the input file.
     #[structopt(parse(from_os_str))]
     #[structopt(short = "i", long = "input")]
-    input_path: PathBuf,
+    input_path: Option<PathBuf>,
     #[structopt(parse(from_os_str))]
     #[structopt(short = "o", long = "output")]
     /// The path to the output file.
-    output_path: PathBuf,
+    output_path: Option<PathBuf>,
     /// The size, in pixels, of a glyph slot in the font sheet. The slot glyph
     /// is not necessarily the same as the glyph size because a glyph slot can contain padding.
     #[structopt(long = "slot-glyph-size", default_value = "64")]
@@ -433,24 +1494,387 @@ struct Opt {
     #[structopt(long = "origin", default_value = "bottom-left")]
     #[structopt(parse(try_from_str = "parse_origin"))]
     origin: bmfa::Origin,
+    /// A comma separated list of slot glyph sizes, in pixels. When this is present, `fontgen`
+    /// generates one atlas per size, named after the output file with the size appended,
+    /// instead of a single atlas at `--slot-glyph-size`.
+    #[structopt(long = "sizes")]
+    #[structopt(raw(use_delimiter = "true"))]
+    sizes: Vec<usize>,
+    /// Pack the atlases for every size given in `--sizes` into a single shared texture. The
+    /// texture is written out as a PNG image next to a JSON file holding the glyph metadata
+    /// keyed by glyph size and code point.
+    #[structopt(long = "shared-atlas")]
+    shared_atlas: bool,
+    /// Write a copy of the atlas image with the glyph slots, the padding, the glyph rectangles
+    /// recorded in the metadata, and the glyph baselines drawn over it to this PNG file. When
+    /// generating several sizes, the size is appended to the file name.
+    #[structopt(parse(from_os_str))]
+    #[structopt(long = "debug-overlay")]
+    debug_overlay: Option<PathBuf>,
+    /// The number of worker threads used to rasterize glyphs. Each worker opens its own
+    /// copy of the typeface. The output does not depend on the number of jobs.
+    #[structopt(short = "j", long = "jobs", default_value = "1")]
+    jobs: usize,
+    /// A directory for caching output between runs. When the font, the options, and the
+    /// version of `fontgen` all match a previous run, the cached output is reused. Rendered
+    /// glyphs are cached individually as well, so only new glyphs get rendered.
+    #[structopt(parse(from_os_str))]
+    #[structopt(long = "cache-dir")]
+    cache_dir: Option<PathBuf>,
+    /// Trim each glyph down to the rectangle holding its ink and pack the rectangles tightly,
+    /// instead of placing each glyph in a fixed size slot. The exact pixel rectangle and
+    /// the offset from the pen position of every glyph are written to a `.metrics.json`
+    /// file next to each atlas file.
+    #[structopt(long = "trim")]
+    trim: bool,
+    /// Pack identical glyph images only once, with every code point sharing the image
+    /// pointing at the same rectangle. This requires `--trim`.
+    #[structopt(long = "dedupe")]
+    dedupe: bool,
+    /// The number of pixels to extend each glyph rectangle by on every side, by copying the
+    /// pixels along its edges outward. This keeps texture filtering from mixing neighbouring
+    /// glyphs together. The glyph rectangles in the metadata do not include the extrusion.
+    #[structopt(long = "extrude", default_value = "0")]
+    extrude: usize,
+    /// The number of empty pixels between neighbouring glyph slots, or between neighbouring
+    /// glyph rectangles with `--trim`, outside of any extrusion.
+    #[structopt(long = "spacing", default_value = "0")]
+    spacing: usize,
+    /// The number of mip levels to generate below the atlas image. Each level is downsampled
+    /// one glyph rectangle at a time, so neighbouring glyphs never blend together, and is
+    /// stored in the texture file written with `--image-format`, which is required. The
+    /// glyphs must be far enough apart that no two of them share a texel in the smallest level.
+    #[structopt(long = "mipmaps", default_value = "0")]
+    mipmaps: usize,
+    /// Also write the atlas image, along with any mip levels, to a GPU texture file next to
+    /// the atlas file. This is either `ktx2`, which stores the glyph metadata in its key/value
+    /// data, or `dds`, which gets the glyph metadata in a `.metadata.json` file next to it.
+    #[structopt(long = "image-format")]
+    #[structopt(parse(try_from_str = "texture::parse_image_format"))]
+    image_format: Option<texture::ImageFormat>,
+    /// A comma separated list of formats to also export each atlas to, next to the atlas
+    /// file. With `json`, the atlas is described by the versioned JSON metadata documented
+    /// in `schemas/fontgen-atlas-v1.schema.json`, written to a `.atlas.json` file. With
+    /// `msdf-atlas-json`, it is described in the JSON layout of msdf-atlas-gen, written to
+    /// a `.msdf.json` file. With `rust`, the pixels and glyph metrics are embedded in a Rust
+    /// module written to a `.rs` file, and with `c-header`, the pixels, glyph metrics, and
+    /// kerning pairs are embedded in a C header written to a `.h` file.
+    #[structopt(long = "format")]
+    #[structopt(raw(use_delimiter = "true"))]
+    #[structopt(parse(try_from_str = "export::parse_export_format"))]
+    formats: Vec<export::ExportFormat>,
+    /// The prefix of every symbol in a generated C header. This defaults to the name of the
+    /// output file. When generating several sizes, the size is appended to the prefix.
+    #[structopt(long = "symbol-prefix")]
+    symbol_prefix: Option<String>,
+    /// Render the glyphs in monochrome, with one bit per pixel, for displays that cannot show
+    /// shades of gray. The packed bitmap of each glyph is written to a `.packed.bin` file in
+    /// place of the atlas file, with its offset and size in a `.packed.json` file, and source
+    /// code generated with `--format rust` or `--format c-header` holds the packed bitmaps
+    /// instead of the atlas image.
+    #[structopt(long = "mono")]
+    mono: bool,
+    /// Also write the atlas file of a monochrome atlas, with the glyphs in an RGBA image that
+    /// only holds fully covered and empty pixels. This requires `--mono`.
+    #[structopt(long = "rgba-atlas")]
+    rgba_atlas: bool,
+    /// Render the glyphs anti-aliased and set every pixel with at least this coverage, from 0
+    /// to 255, instead of using the monochrome rasterizer of FreeType. This requires `--mono`.
+    #[structopt(long = "threshold")]
+    threshold: Option<u8>,
+    /// The order of the pixels in each byte of a packed glyph bitmap, either `msb-first` or
+    /// `lsb-first`. This defaults to `msb-first`, and requires `--mono`.
+    #[structopt(long = "bit-order")]
+    #[structopt(parse(try_from_str = "mono::parse_bit_order"))]
+    bit_order: Option<mono::BitOrder>,
+    /// The grouping of the pixels of a packed glyph bitmap into bytes. With `rows`, each byte
+    /// holds eight pixels of a row. With `columns`, each byte holds eight pixels of a column,
+    /// in pages of eight rows, as SSD1306 style displays expect. This defaults to `rows`, and
+    /// requires `--mono`.
+    #[structopt(long = "page-layout")]
+    #[structopt(parse(try_from_str = "mono::parse_page_layout"))]
+    page_layout: Option<mono::PageLayout>,
+    /// Copy the glyphs from the hand drawn bitmap strikes embedded in a TrueType or OpenType
+    /// font, pixel for pixel, instead of rasterizing their outlines. The largest strike that
+    /// fits into the glyph size is used, unless one is picked with `--strike`. Bitmap fonts,
+    /// like BDF and PCF fonts, are always sampled from their strikes.
+    #[structopt(long = "use-embedded-bitmaps")]
+    use_embedded_bitmaps: bool,
+    /// The pixel size of the bitmap strike to copy the glyphs from. The available sizes are
+    /// listed by `fontgen strikes`. This implies `--use-embedded-bitmaps`.
+    #[structopt(long = "strike")]
+    strike: Option<usize>,
+    /// Draw color glyphs, like emoji, in their own colors instead of as coverage. This covers
+    /// the BGRA bitmaps of CBDT and sbix fonts, which get scaled to the glyph size, and the
+    /// layered glyphs of COLR fonts, composited in the colors of the default palette. Each
+    /// pixel of a color glyph holds its premultiplied color, and color glyphs are marked in
+    /// the `.metrics.json` file next to each atlas file, so they can be drawn without tinting.
+    #[structopt(long = "color")]
+    color: bool,
+    /// A comma separated list of OpenType features to apply from the GSUB table of the font,
+    /// like `liga`, `calt`, `smcp`, or `onum`. Every glyph the features substitute in, such as
+    /// ligatures, alternates, and small capitals, is added to the atlas, keyed by its glyph
+    /// index from U+F0000 up, and the substitutions are written to a `.substitutions.json`
+    /// file next to each atlas file. This requires `--trim`.
+    #[structopt(long = "features")]
+    #[structopt(raw(use_delimiter = "true"))]
+    #[structopt(parse(try_from_str = "gsub::parse_feature_tag"))]
+    features: Vec<String>,
+    /// A comma separated list of glyph indices, or ranges of them like `1-500`, to add to the
+    /// atlas by glyph index, such as alternates and icons that no code point maps to. Each glyph
+    /// is loaded straight from the font, and keyed by its glyph index from U+F0000 up, unless a
+    /// printable code point already puts it in the atlas. This requires `--trim`.
+    #[structopt(long = "glyph-ids")]
+    #[structopt(raw(use_delimiter = "true"))]
+    #[structopt(parse(try_from_str = "glyph_ids::parse_glyph_id_range"))]
+    glyph_ids: Vec<glyph_ids::GlyphIdRange>,
+    /// Add every glyph in the font to the atlas, the way `--glyph-ids` does. This requires
+    /// `--trim`.
+    #[structopt(long = "all-glyphs")]
+    all_glyphs: bool,
+    /// A comma separated list of glyph names to add to the atlas, the way `--glyph-ids` does.
+    /// Names in the file given with `--glyph-name-map` stand for the glyph their code point
+    /// maps to, and other names are looked up among the PostScript glyph names in the `post`
+    /// table of the font. The metrics of these glyphs are written to a `.names.json` file next
+    /// to each atlas file, keyed by name. This requires `--trim`.
+    #[structopt(long = "glyph-names")]
+    #[structopt(raw(use_delimiter = "true"))]
+    glyph_names: Vec<String>,
+    /// A file mapping glyph names to code points, for icon fonts that give their glyphs no
+    /// names of their own. Each line holds a name and a code point in hexadecimal, like
+    /// `fa-heart f004`, the way the `codepoints` file of Material Icons does. Without
+    /// `--glyph-names`, every name in the file is added to the atlas.
+    #[structopt(parse(from_os_str))]
+    #[structopt(long = "glyph-name-map")]
+    glyph_name_map: Option<PathBuf>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+impl Opt {
+    /// The path to the input file. An input file is required whenever no subcommand
+    /// is given, which `verify_opt` checks before the path is used.
+    fn input_path(&self) -> &Path {
+        self.input_path.as_ref().expect("The input file is required without a subcommand.")
+    }
+
+    /// The path to the output file. An output file is required whenever no subcommand
+    /// is given, which `verify_opt` checks before the path is used.
+    fn output_path(&self) -> &Path {
+        self.output_path.as_ref().expect("The output file is required without a subcommand.")
+    }
+
+    /// Whether the atlas needs a metrics file next to it. That is the case when glyphs are
+    /// trimmed or separated by a gutter, or when the atlas may hold color glyphs, since the
+    /// atlas metadata cannot describe any of them.
+    fn writes_metrics(&self) -> bool {
+        self.trim || self.extrude > 0 || self.spacing > 0 || self.color
+    }
+
+    /// Whether the atlas file gets written. A monochrome atlas is written as packed bitmaps
+    /// instead, unless its atlas file is asked for.
+    fn writes_atlas_file(&self) -> bool {
+        !self.mono || self.rgba_atlas
+    }
+
+    /// Whether any glyphs get added to the atlas by name.
+    fn picks_glyphs_by_name(&self) -> bool {
+        !self.glyph_names.is_empty() || self.glyph_name_map.is_some()
+    }
+
+    /// The way each glyph image is rasterized.
+    fn rendering(&self) -> GlyphRendering {
+        if self.color {
+            return GlyphRendering::Color;
+        }
+        match (self.mono, self.threshold) {
+            (false, _) => GlyphRendering::Coverage,
+            (true, None) => GlyphRendering::Mono,
+            (true, Some(threshold)) => GlyphRendering::Threshold(threshold),
+        }
+    }
+
+    /// The way the glyph bitmaps of a monochrome atlas are packed into bytes.
+    fn mono_packing(&self) -> Option<mono::MonoPacking> {
+        if !self.mono {
+            return None;
+        }
+
+        Some(mono::MonoPacking {
+            bit_order: self.bit_order.unwrap_or(mono::BitOrder::MsbFirst),
+            page_layout: self.page_layout.unwrap_or(mono::PageLayout::Rows),
+        })
+    }
+
+    /// The slot glyph sizes to generate atlases for.
+    fn slot_glyph_sizes(&self) -> Vec<usize> {
+        if self.sizes.is_empty() {
+            vec![self.slot_glyph_size]
+        } else {
+            self.sizes.clone()
+        }
+    }
+}
+
+/// The path to an output file for a single slot glyph size when generating several sizes
+/// in one run.
+fn sized_path(output_path: &Path, slot_glyph_size: usize, extension: &str) -> PathBuf {
+    let stem = match output_path.file_stem() {
+        Some(stem) => stem.to_string_lossy().into_owned(),
+        None => String::from("atlas"),
+    };
+
+    output_path.with_file_name(format!("{}-{}.{}", stem, slot_glyph_size, extension))
+}
+
+/// The path to the atlas file for a single slot glyph size.
+fn atlas_path(opt: &Opt, slot_glyph_size: usize) -> PathBuf {
+    if opt.sizes.is_empty() {
+        let mut atlas_file = opt.output_path().to_path_buf();
+        atlas_file.set_extension("bmfa");
+        atlas_file
+    } else {
+        sized_path(opt.output_path(), slot_glyph_size, "bmfa")
+    }
+}
+
+/// The paths to the image file and the metadata file of a shared atlas.
+fn shared_atlas_paths(opt: &Opt) -> (PathBuf, PathBuf) {
+    let mut image_file = opt.output_path().to_path_buf();
+    image_file.set_extension("png");
+    let mut metadata_file = opt.output_path().to_path_buf();
+    metadata_file.set_extension("json");
+
+    (image_file, metadata_file)
+}
+
+/// The prefix of the symbols in source code generated for a single slot glyph size.
+fn symbol_prefix(opt: &Opt, slot_glyph_size: usize) -> String {
+    let prefix = match opt.symbol_prefix {
+        Some(ref prefix) => prefix.clone(),
+        None => {
+            let stem = opt.output_path().file_stem().unwrap_or_default().to_string_lossy();
+            codegen::c_identifier(&stem)
+        }
+    };
+    if opt.sizes.is_empty() {
+        prefix
+    } else {
+        format!("{}_{}", prefix, slot_glyph_size)
+    }
+}
+
+/// The path to the debug overlay image for a single slot glyph size.
+fn debug_overlay_path(opt: &Opt, slot_glyph_size: usize) -> Option<PathBuf> {
+    match opt.debug_overlay {
+        Some(ref path) if opt.sizes.is_empty() => Some(path.clone()),
+        Some(ref path) => Some(sized_path(path, slot_glyph_size, "png")),
+        None => None,
+    }
 }
 
 /// Verify the input options.
 fn verify_opt(opt: &Opt) -> Result<(), OptError> {
-    if !opt.input_path.exists() {
-        return Err(OptError::InputFileDoesNotExist(opt.input_path.clone()));
+    if opt.input_path.is_none() {
+        return Err(OptError::InputFileRequired);
+    }
+    if opt.output_path.is_none() {
+        return Err(OptError::OutputFileRequired);
+    }
+    if !opt.input_path().exists() {
+        return Err(OptError::InputFileDoesNotExist(opt.input_path().to_path_buf()));
+    }
+    if !opt.input_path().is_file() {
+        return Err(OptError::InputFileIsNotAFile(opt.input_path().to_path_buf()));
+    }
+    if opt.shared_atlas && opt.sizes.is_empty() {
+        return Err(OptError::SharedAtlasRequiresSizes);
     }
-    if !opt.input_path.is_file() {
-        return Err(OptError::InputFileIsNotAFile(opt.input_path.clone()));
+    if opt.shared_atlas && opt.writes_metrics() {
+        return Err(OptError::SharedAtlasRequiresPlainGrid);
     }
-    if opt.output_path.exists() {
-        return Err(OptError::OutputFileExists(opt.output_path.clone()));
+    if opt.mipmaps > 0 && opt.image_format.is_none() {
+        return Err(OptError::MipmapsRequireImageFormat);
     }
-    if !(opt.slot_glyph_size > 0) {
-        return Err(OptError::SlotGlyphSizeCannotBeZero(opt.slot_glyph_size));
+    if opt.shared_atlas && opt.image_format.is_some() {
+        return Err(OptError::ImageFormatRequiresAtlasFile);
     }
-    if opt.padding > opt.slot_glyph_size {
-        return Err(OptError::PaddingLargerThanSlotGlyphSize(opt.padding, opt.slot_glyph_size));
+    if opt.shared_atlas && !opt.formats.is_empty() {
+        return Err(OptError::ExportRequiresAtlasFile);
+    }
+    if let Some(ref prefix) = opt.symbol_prefix {
+        if !codegen::is_c_identifier(prefix) {
+            return Err(OptError::InvalidSymbolPrefix(prefix.clone()));
+        }
+    }
+    if opt.shared_atlas && opt.mono {
+        return Err(OptError::MonoRequiresAtlasFile);
+    }
+    if !opt.mono && (opt.threshold.is_some() || opt.bit_order.is_some() || opt.page_layout.is_some() || opt.rgba_atlas) {
+        return Err(OptError::MonoOptionRequiresMono);
+    }
+    if !opt.writes_atlas_file() {
+        // These describe the atlas image, which is only in the atlas file.
+        for &format in opt.formats.iter() {
+            if format == export::ExportFormat::Json || format == export::ExportFormat::MsdfAtlasJson {
+                return Err(OptError::ImageExportRequiresRgbaAtlas(String::from(format.name())));
+            }
+        }
+    }
+    if opt.color && opt.mono {
+        return Err(OptError::ColorConflictsWithMono);
+    }
+    if opt.color {
+        // Generated source code only holds the coverage of each pixel.
+        for &format in opt.formats.iter() {
+            if format == export::ExportFormat::Rust || format == export::ExportFormat::CHeader {
+                return Err(OptError::ColorRequiresImageExport(String::from(format.name())));
+            }
+        }
+    }
+    if opt.dedupe && !opt.trim {
+        return Err(OptError::DedupeRequiresTrim);
+    }
+    if !opt.features.is_empty() && !opt.trim {
+        return Err(OptError::FeaturesRequireTrim);
+    }
+    if (!opt.glyph_ids.is_empty() || opt.all_glyphs || opt.picks_glyphs_by_name()) && !opt.trim {
+        return Err(OptError::GlyphIdsRequireTrim);
+    }
+    if opt.jobs == 0 {
+        return Err(OptError::JobsCannotBeZero);
+    }
+    for slot_glyph_size in opt.slot_glyph_sizes() {
+        if !(slot_glyph_size > 0) {
+            return Err(OptError::SlotGlyphSizeCannotBeZero(slot_glyph_size));
+        }
+        if opt.padding > slot_glyph_size {
+            return Err(OptError::PaddingLargerThanSlotGlyphSize(opt.padding, slot_glyph_size));
+        }
+        if let (Some(strike), false) = (opt.strike, opt.color) {
+            let glyph_size = atlas_spec(opt, slot_glyph_size).glyph_size;
+            if strike > glyph_size {
+                return Err(OptError::StrikeLargerThanGlyphSize(strike, glyph_size));
+            }
+        }
+        let atlas_width = atlas_spec(opt, slot_glyph_size).width;
+        if 1_usize.checked_shl(opt.mipmaps as u32).map_or(true, |scale| scale > atlas_width) {
+            return Err(OptError::TooManyMipLevels(opt.mipmaps, atlas_width));
+        }
+    }
+
+    Ok(())
+}
+
+/// Verify that running `fontgen` will not overwrite any existing files.
+fn verify_output_paths(opt: &Opt) -> Result<(), OptError> {
+    if opt.output_path().exists() {
+        return Err(OptError::OutputFileExists(opt.output_path().to_path_buf()));
+    }
+    for (_, output_file) in output_files(opt) {
+        if output_file.exists() {
+            return Err(OptError::OutputFileExists(output_file));
+        }
     }
 
     Ok(())
@@ -461,6 +1885,18 @@ enum AppError {
     CouldNotOpenFontFile(PathBuf),
     CouldNotCreateBitmapFont(Box<dyn std::error::Error>),
     CouldNotCreateAtlasFile(PathBuf),
+    CouldNotOpenAtlasFile(PathBuf),
+    CouldNotUseCache(PathBuf),
+    NoBitmapStrikes(PathBuf),
+    NoMatchingStrike(usize, String),
+    NoStrikeFitsGlyphSize(usize, String),
+    NoGsubTable(PathBuf),
+    GlyphIdOutOfRange(u32, u32),
+    CouldNotReadGlyphNameMap(PathBuf),
+    InvalidGlyphNameMapLine(PathBuf, usize),
+    NoGlyphNamed(String),
+    NoGlyphNamedInMap(String, PathBuf),
+    NoGlyphForMappedName(String, usize),
 }
 
 impl fmt::Display for AppError {
@@ -475,46 +1911,505 @@ impl fmt::Display for AppError {
             AppError::CouldNotCreateAtlasFile(atlas_file) => {
                 write!(f, "Could not create atlas file: {}.", atlas_file.display())
             }
+            AppError::CouldNotOpenAtlasFile(atlas_file) => {
+                write!(f, "Could not open atlas file: {}.", atlas_file.display())
+            }
+            AppError::CouldNotUseCache(cache_dir) => {
+                write!(f, "Could not read or write the cache directory: {}.", cache_dir.display())
+            }
+            AppError::NoBitmapStrikes(input_path) => {
+                write!(f, "The font file {} has no bitmap strikes.", input_path.display())
+            }
+            AppError::NoMatchingStrike(strike, sizes) => {
+                write!(f, "The font has no {} pixel bitmap strike. The available strike sizes are: {}.", strike, sizes)
+            }
+            AppError::NoStrikeFitsGlyphSize(glyph_size, sizes) => {
+                write!(
+                    f,
+                    "No bitmap strike fits into the glyph size ({} pixels). The available strike sizes are: {}.",
+                    glyph_size, sizes
+                )
+            }
+            AppError::NoGsubTable(input_path) => {
+                write!(f, "The font file {} has no GSUB table to apply features from.", input_path.display())
+            }
+            AppError::GlyphIdOutOfRange(glyph_id, glyph_count) => {
+                write!(f, "The font has {} glyphs, so it has no glyph with glyph index {}.", glyph_count, glyph_id)
+            }
+            AppError::CouldNotReadGlyphNameMap(path) => {
+                write!(f, "Could not read glyph name map: {}.", path.display())
+            }
+            AppError::InvalidGlyphNameMapLine(path, line) => {
+                write!(
+                    f,
+                    "Line {} of the glyph name map {} must hold a name and a code point in hexadecimal.",
+                    line, path.display()
+                )
+            }
+            AppError::NoGlyphNamed(name) => {
+                write!(f, "The font has no glyph named {}.", name)
+            }
+            AppError::NoGlyphNamedInMap(name, path) => {
+                write!(
+                    f,
+                    "The font has no glyph named {}, and the glyph name map {} does not name it either.",
+                    name, path.display()
+                )
+            }
+            AppError::NoGlyphForMappedName(name, code_point) => {
+                write!(
+                    f,
+                    "The glyph name map maps {} to U+{:04X}, but the font has no glyph for that code point.",
+                    name, code_point
+                )
+            }
         }
     }
 }
 
 impl error::Error for AppError {}
 
-/// Run the application.
-fn run_app(opt: &Opt) -> Result<(), Box<dyn std::error::Error>> {
-    let ft = Library::init().expect("Failed to initialize FreeType library.");
-    let face = match ft.new_face(&opt.input_path, 0) {
-        Ok(val) => val,
-        Err(_) => {
-            return Err(Box::new(AppError::CouldNotOpenFontFile(opt.input_path.clone())));
-        }
-    };
-
+/// Construct the atlas specification for a single slot glyph size.
+fn atlas_spec(opt: &Opt, slot_glyph_size: usize) -> AtlasSpec {
     let origin = opt.origin;
-    let slot_glyph_size = opt.slot_glyph_size;
     let atlas_columns = 16;
     let atlas_rows = 16;
-    let atlas_height_px = slot_glyph_size * atlas_rows;
-    let atlas_width_px = slot_glyph_size * atlas_columns;
+    // Every slot carries its extrusion on both sides, and the spacing sits between slots.
+    let gutter_px = 2 * opt.extrude;
+    let atlas_height_px = (slot_glyph_size + gutter_px) * atlas_rows + opt.spacing * (atlas_rows - 1);
+    let atlas_width_px = (slot_glyph_size + gutter_px) * atlas_columns + opt.spacing * (atlas_columns - 1);
     let padding_px = opt.padding;
     let atlas_glyph_px = slot_glyph_size - padding_px;
-    let mut atlas_file = opt.output_path.clone();
-    atlas_file.set_extension("bmfa");
 
-    let atlas_spec = AtlasSpec::new(
+    AtlasSpec::new(
         origin, atlas_width_px, atlas_height_px,
-        atlas_rows, atlas_columns, padding_px, slot_glyph_size, atlas_glyph_px
-    );
-    let atlas = match create_bitmap_atlas(face, atlas_spec) {
+        atlas_rows, atlas_columns, padding_px, slot_glyph_size, atlas_glyph_px,
+        opt.extrude, opt.spacing
+    )
+}
+
+/// The files written by a run of `fontgen`, each paired with the name it is stored under
+/// in the cache.
+fn output_files(opt: &Opt) -> Vec<(String, PathBuf)> {
+    let mut files = vec![];
+    if opt.shared_atlas {
+        let (image_file, metadata_file) = shared_atlas_paths(opt);
+        files.push((String::from("shared.png"), image_file));
+        files.push((String::from("shared.json"), metadata_file));
+    } else {
+        for slot_glyph_size in opt.slot_glyph_sizes() {
+            if opt.writes_atlas_file() {
+                files.push((format!("atlas-{}.bmfa", slot_glyph_size), atlas_path(opt, slot_glyph_size)));
+            }
+            if opt.writes_metrics() {
+                let metrics_file = atlas_metrics_path(&atlas_path(opt, slot_glyph_size));
+                files.push((format!("metrics-{}.json", slot_glyph_size), metrics_file));
+            }
+            if opt.picks_glyphs_by_name() {
+                let names_file = glyph_names_path(&atlas_path(opt, slot_glyph_size));
+                files.push((format!("names-{}.json", slot_glyph_size), names_file));
+            }
+            if opt.mono {
+                let bitmaps_file = packed_bitmaps_path(&atlas_path(opt, slot_glyph_size));
+                let table_file = packed_table_path(&atlas_path(opt, slot_glyph_size));
+                files.push((format!("packed-{}.bin", slot_glyph_size), bitmaps_file));
+                files.push((format!("packed-{}.json", slot_glyph_size), table_file));
+            }
+            if !opt.features.is_empty() {
+                let substitutions_file = substitutions_path(&atlas_path(opt, slot_glyph_size));
+                files.push((format!("substitutions-{}.json", slot_glyph_size), substitutions_file));
+            }
+            if let Some(image_format) = opt.image_format {
+                let texture_file = texture_path(&atlas_path(opt, slot_glyph_size), image_format);
+                files.push((format!("texture-{}.{}", slot_glyph_size, image_format.name()), texture_file));
+                if image_format == texture::ImageFormat::Dds {
+                    let metadata_file = texture_metadata_path(&atlas_path(opt, slot_glyph_size));
+                    files.push((format!("metadata-{}.json", slot_glyph_size), metadata_file));
+                }
+            }
+            for &format in opt.formats.iter() {
+                let export_file = export::export_path(&atlas_path(opt, slot_glyph_size), format);
+                files.push((format!("export-{}.{}", slot_glyph_size, format.extension()), export_file));
+            }
+        }
+    }
+    for slot_glyph_size in opt.slot_glyph_sizes() {
+        if let Some(overlay_file) = debug_overlay_path(opt, slot_glyph_size) {
+            files.push((format!("overlay-{}.png", slot_glyph_size), overlay_file));
+        }
+    }
+
+    files
+}
+
+/// The cache key for the output of a run. This covers the font file and every option that
+/// changes the output, but not the output file names or the number of jobs.
+fn output_cache_key(opt: &Opt, font_key: u64) -> u64 {
+    let mut hasher = cache::CacheKeyHasher::new();
+    hasher.write(&font_key.to_le_bytes());
+    for slot_glyph_size in opt.slot_glyph_sizes() {
+        hasher.write_usize(slot_glyph_size);
+    }
+    hasher.write_str("padding");
+    hasher.write_usize(opt.padding);
+    hasher.write_str(origin_name(opt.origin));
+    hasher.write_str(if opt.shared_atlas { "shared-atlas" } else { "separate-atlases" });
+    hasher.write_str(if opt.debug_overlay.is_some() { "debug-overlay" } else { "no-debug-overlay" });
+    hasher.write_str(if opt.trim { "trim" } else { "no-trim" });
+    hasher.write_str(if opt.dedupe { "dedupe" } else { "no-dedupe" });
+    hasher.write_str("extrude");
+    hasher.write_usize(opt.extrude);
+    hasher.write_str("spacing");
+    hasher.write_usize(opt.spacing);
+    hasher.write_str("mipmaps");
+    hasher.write_usize(opt.mipmaps);
+    hasher.write_str(opt.image_format.map_or("no-image-format", |image_format| image_format.name()));
+    hasher.write_str(&opt.rendering().name());
+    hasher.write_str(if opt.use_embedded_bitmaps { "embedded-bitmaps" } else { "outlines" });
+    hasher.write_str("strike");
+    hasher.write_usize(opt.strike.unwrap_or(0));
+    hasher.write_str("features");
+    for feature in opt.features.iter() {
+        hasher.write_str(feature);
+    }
+    hasher.write_str(if opt.all_glyphs { "all-glyphs" } else { "glyph-ids" });
+    for range in opt.glyph_ids.iter() {
+        hasher.write_usize(range.first as usize);
+        hasher.write_usize(range.last as usize);
+    }
+    hasher.write_str("glyph-names");
+    for name in opt.glyph_names.iter() {
+        hasher.write_str(name);
+    }
+    // The names in the glyph name map can change without its path changing.
+    if let Some(ref glyph_name_map) = opt.glyph_name_map {
+        hasher.write(&fs::read(glyph_name_map).unwrap_or_default());
+    }
+    if let Some(packing) = opt.mono_packing() {
+        hasher.write_str(packing.bit_order.name());
+        hasher.write_str(packing.page_layout.name());
+        hasher.write_str(if opt.rgba_atlas { "rgba-atlas" } else { "no-rgba-atlas" });
+    }
+    hasher.write_str("formats");
+    for format in opt.formats.iter() {
+        hasher.write_str(format.name());
+    }
+    if !opt.formats.is_empty() {
+        // Exported files refer to the atlas file by name, and generated source code names
+        // its symbols after the output file.
+        for slot_glyph_size in opt.slot_glyph_sizes() {
+            hasher.write_str(&atlas_path(opt, slot_glyph_size).file_name().unwrap_or_default().to_string_lossy());
+            hasher.write_str(&symbol_prefix(opt, slot_glyph_size));
+        }
+    }
+
+    hasher.finish()
+}
+
+/// Choose the bitmap strike to copy the glyphs of a single glyph size from. Fonts without
+/// outlines can only be sampled from a strike, and other fonts only are with
+/// `--use-embedded-bitmaps`. Color glyphs get scaled to the glyph size, so any strike will do
+/// for them.
+fn glyph_strike(
+    opt: &Opt, face: &freetype::face::Face, glyph_size: usize) -> Result<Option<strikes::Strike>, AppError> {
+
+    if !opt.use_embedded_bitmaps && opt.strike.is_none() && face.is_scalable() {
+        return Ok(None);
+    }
+    let font_strikes = strikes::font_strikes(face);
+    if font_strikes.is_empty() {
+        return Err(AppError::NoBitmapStrikes(opt.input_path().to_path_buf()));
+    }
+    let strike = if opt.color {
+        strikes::choose_strike_to_scale(&font_strikes, opt.strike, glyph_size)
+    } else {
+        strikes::choose_strike(&font_strikes, opt.strike, glyph_size)
+    };
+    match strike {
+        Some(strike) => {
+            println!(
+                "Using the {} pixel bitmap strike at glyph size {}. Available strike sizes: {}.",
+                strike.pixel_size, glyph_size, strikes::strike_sizes(&font_strikes)
+            );
+            Ok(Some(strike))
+        }
+        None => match opt.strike {
+            Some(pixel_size) => Err(AppError::NoMatchingStrike(pixel_size, strikes::strike_sizes(&font_strikes))),
+            None => Err(AppError::NoStrikeFitsGlyphSize(glyph_size, strikes::strike_sizes(&font_strikes))),
+        },
+    }
+}
+
+/// Generate the atlases for every requested size and write them out.
+fn generate_atlases(
+    opt: &Opt, cache: Option<&cache::Cache>, font_key: u64) -> Result<(), Box<dyn std::error::Error>> {
+
+    let ft = Library::init().expect("Failed to initialize FreeType library.");
+    let face = match ft.new_face(opt.input_path(), 0) {
         Ok(val) => val,
-        Err(e) => {
-            return Err(Box::new(AppError::CouldNotCreateBitmapFont(Box::new(e))));
+        Err(_) => {
+            return Err(Box::new(AppError::CouldNotOpenFontFile(opt.input_path().to_path_buf())));
         }
     };
 
-    if bmfa::write_to_file(&atlas_file, &atlas).is_err() {
-        return Err(Box::new(AppError::CouldNotCreateAtlasFile(atlas_file)));
+    // The glyphs the code points put into the atlas, including the space. A glyph that several
+    // code points map to goes by the lowest of them.
+    let mut code_points = HashMap::new();
+    for code_point in 32..256 {
+        let glyph_index = face.get_char_index(code_point);
+        if glyph_index != 0 {
+            code_points.entry(glyph_index).or_insert(code_point);
+        }
+    }
+    // The substitutions only depend on the font, so every size gets the same ones.
+    let substitution_table = if opt.features.is_empty() {
+        None
+    } else {
+        let gsub_table = match gsub::load_gsub_table(&face) {
+            Some(val) => val,
+            None => return Err(Box::new(AppError::NoGsubTable(opt.input_path().to_path_buf()))),
+        };
+        let substitutions = gsub::feature_substitutions(&gsub_table, &opt.features);
+        let substitution_table = gsub::SubstitutionTable::new(&opt.features, substitutions, &code_points);
+        println!(
+            "Found {} substitutions for the features {}, adding {} glyphs.",
+            substitution_table.substitutions.len(), opt.features.join(", "),
+            substitution_table.glyph_index_keys().len()
+        );
+        Some(substitution_table)
+    };
+    let mut glyph_index_keys = substitution_table.as_ref()
+        .map_or(BTreeSet::new(), |substitution_table| substitution_table.glyph_index_keys());
+    let glyph_count = glyph_ids::glyph_count(&face);
+    let mut glyph_id_ranges = opt.glyph_ids.clone();
+    if opt.all_glyphs && glyph_count > 0 {
+        glyph_id_ranges.push(glyph_ids::GlyphIdRange { first: 0, last: glyph_count - 1 });
+    }
+    for range in glyph_id_ranges.iter() {
+        if range.last >= glyph_count {
+            return Err(Box::new(AppError::GlyphIdOutOfRange(range.last, glyph_count)));
+        }
+        let glyph_indices = (range.first..(range.last + 1)).filter(|glyph_index| !code_points.contains_key(glyph_index));
+        glyph_index_keys.extend(glyph_indices.map(glyph_index_key));
+    }
+    let mut named_keys = vec![];
+    if opt.picks_glyphs_by_name() {
+        let glyph_name_map = match opt.glyph_name_map {
+            Some(ref path) => glyph_names::load_glyph_name_map(path)?,
+            None => BTreeMap::new(),
+        };
+        let names = if opt.glyph_names.is_empty() {
+            glyph_name_map.keys().cloned().collect()
+        } else {
+            opt.glyph_names.clone()
+        };
+        for (name, glyph_index) in glyph_names::glyphs_by_name(
+            &face, &names, &glyph_name_map, opt.glyph_name_map.as_deref()
+        )? {
+            let key = match code_points.get(&glyph_index) {
+                Some(&code_point) => code_point,
+                None => glyph_index_key(glyph_index),
+            };
+            if is_glyph_index_key(key) {
+                glyph_index_keys.insert(key);
+            }
+            named_keys.push((name, key));
+        }
+    }
+    let glyph_index_keys: Vec<usize> = glyph_index_keys.into_iter().collect();
+
+    // Every atlas is laid out and checked before anything gets written, so an atlas that
+    // cannot be written does not leave the files of the other sizes behind.
+    let mut laid_out_atlases = vec![];
+    for slot_glyph_size in opt.slot_glyph_sizes() {
+        let atlas_spec = atlas_spec(opt, slot_glyph_size);
+        let strike = glyph_strike(opt, &face, atlas_spec.glyph_size)?;
+        let rendering_name = match strike {
+            Some(strike) => format!("{}-strike-{}", opt.rendering().name(), strike.pixel_size),
+            None => opt.rendering().name(),
+        };
+        // Glyphs copied from a strike are the size they were drawn at, unless they get scaled
+        // as color glyphs.
+        let glyph_size = match strike {
+            Some(strike) if !opt.color => strike.pixel_size,
+            _ => atlas_spec.glyph_size,
+        };
+        let glyph_cache = match cache {
+            Some(cache) => match cache.glyph_cache(font_key, atlas_spec.glyph_size, &rendering_name) {
+                Ok(val) => Some(val),
+                Err(_) => return Err(Box::new(AppError::CouldNotUseCache(cache.dir().to_path_buf()))),
+            },
+            None => None,
+        };
+        let glyph_tab = match sample_typeface(
+            &face, opt.input_path(), atlas_spec, opt.rendering(), strike, opt.jobs,
+            &glyph_index_keys, glyph_cache.as_ref()) {
+
+            Ok(val) => val,
+            Err(e) => {
+                return Err(Box::new(AppError::CouldNotCreateBitmapFont(Box::new(e))));
+            }
+        };
+        let atlas_layout = if opt.trim {
+            layout::trimmed_layout(&glyph_tab, atlas_spec, opt.dedupe)
+        } else {
+            layout::grid_layout(&glyph_tab, atlas_spec)
+        };
+        if opt.dedupe {
+            println!(
+                "Deduplicated {} glyph images at slot glyph size {}, saving {} pixels.",
+                atlas_layout.shared_glyphs, slot_glyph_size, atlas_layout.saved_area
+            );
+        }
+        let atlas = create_bitmap_atlas(&glyph_tab, &atlas_layout);
+        let mip_chain = match mipmap::create_mip_chain(&atlas_layout, &atlas.image, opt.mipmaps) {
+            Ok(val) => val,
+            Err(overlap) => {
+                let error = OptError::MipLevelsOverlap(overlap.level, overlap.code_point, overlap.other_code_point);
+                // The error only names the glyphs, so say how to keep them apart as well.
+                eprintln!("{}", error);
+                return Err(Box::new(error));
+            }
+        };
+        laid_out_atlases.push((slot_glyph_size, glyph_size, glyph_tab, atlas_layout, atlas, mip_chain));
+    }
+
+    let mut atlases = vec![];
+    for (slot_glyph_size, glyph_size, glyph_tab, atlas_layout, atlas, mip_chain) in laid_out_atlases {
+        if let Some(overlay_file) = debug_overlay_path(opt, slot_glyph_size) {
+            let overlay = overlay::create_debug_overlay(&glyph_tab, &atlas_layout, &atlas);
+            if overlay.save(&overlay_file).is_err() {
+                return Err(Box::new(AppError::CouldNotCreateAtlasFile(overlay_file)));
+            }
+        }
+        let atlas_file = atlas_path(opt, slot_glyph_size);
+        let mut metrics = None;
+        if opt.writes_metrics() {
+            let atlas_metrics = create_atlas_metrics(&glyph_tab, &atlas_layout);
+            atlas_metrics.write_to_file(&atlas_metrics_path(&atlas_file))?;
+            metrics = Some(atlas_metrics);
+        }
+        if let Some(ref substitution_table) = substitution_table {
+            substitution_table.write_to_file(&substitutions_path(&atlas_file))?;
+        }
+        if let Some(ref metrics) = metrics {
+            if opt.picks_glyphs_by_name() {
+                glyph_names::write_glyph_name_table(&glyph_names_path(&atlas_file), &named_keys, &metrics.glyphs)?;
+            }
+        }
+        if let Some(image_format) = opt.image_format {
+            let metadata = OrderedAtlasMetadata::from(&atlas.metadata);
+            let mut key_values = vec![("fontgen.metadata", serde_json::to_vec(&metadata)?)];
+            if let Some(ref metrics) = metrics {
+                key_values.push(("fontgen.metrics", serde_json::to_vec(metrics)?));
+            }
+            let texture_file = texture_path(&atlas_file, image_format);
+            let texture_result = texture::write_texture(
+                &texture_file, image_format, &atlas.image, &mip_chain, &key_values
+            );
+            if texture_result.is_err() {
+                return Err(Box::new(AppError::CouldNotCreateAtlasFile(texture_file)));
+            }
+            if image_format == texture::ImageFormat::Dds {
+                let metadata_file = texture_metadata_path(&atlas_file);
+                let file = match File::create(&metadata_file) {
+                    Ok(val) => val,
+                    Err(_) => return Err(Box::new(AppError::CouldNotCreateAtlasFile(metadata_file))),
+                };
+                if serde_json::to_writer_pretty(file, &metadata).is_err() {
+                    return Err(Box::new(AppError::CouldNotCreateAtlasFile(metadata_file)));
+                }
+            }
+        }
+        let metrics = match metrics {
+            Some(val) => val,
+            None => create_atlas_metrics(&glyph_tab, &atlas_layout),
+        };
+        if let Some(packing) = opt.mono_packing() {
+            let bitmaps = mono::pack_atlas(packing, &atlas.image, &metrics.glyphs);
+            mono::write_packed_bitmaps(
+                &packed_bitmaps_path(&atlas_file), &packed_table_path(&atlas_file),
+                packing, &bitmaps, &metrics.glyphs
+            )?;
+        }
+        if !opt.formats.is_empty() {
+            let metadata = OrderedAtlasMetadata::from(&atlas.metadata);
+            let font = export::font_info(&face, glyph_size);
+            let keys: Vec<usize> = metrics.glyphs.keys().cloned().collect();
+            let kerning = export::kerning_pairs(&face, &keys, glyph_size);
+            let export = export::AtlasExport {
+                atlas_file: atlas_file.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                font: &font,
+                metadata: &metadata,
+                metrics: &metrics,
+                kerning: &kerning,
+                image: &atlas.image,
+                symbol_prefix: symbol_prefix(opt, slot_glyph_size),
+                mono: opt.mono_packing(),
+            };
+            for &format in opt.formats.iter() {
+                export::write_export(&export::export_path(&atlas_file, format), format, &export)?;
+            }
+        }
+        atlases.push(atlas);
+    }
+
+    if opt.shared_atlas {
+        let (image_file, metadata_file) = shared_atlas_paths(opt);
+        let shared_atlas = create_shared_atlas(&atlases, opt.origin);
+
+        write_shared_atlas(&image_file, &metadata_file, &shared_atlas)?;
+
+        return Ok(());
+    }
+
+    if !opt.writes_atlas_file() {
+        return Ok(());
+    }
+    for atlas in atlases.iter() {
+        let atlas_file = atlas_path(opt, atlas.metadata.slot_glyph_size);
+        atlas_file::write_atlas_file(&atlas_file, atlas)?;
+    }
+
+    Ok(())
+}
+
+/// Run the application.
+fn run_app(opt: &Opt) -> Result<(), Box<dyn std::error::Error>> {
+    let cache_dir = match opt.cache_dir {
+        Some(ref cache_dir) => cache_dir,
+        None => return generate_atlases(opt, None, 0),
+    };
+    let cache = match cache::Cache::open(cache_dir) {
+        Ok(val) => val,
+        Err(_) => return Err(Box::new(AppError::CouldNotUseCache(cache_dir.clone()))),
+    };
+    let font_key = match fs::read(opt.input_path()) {
+        Ok(font_bytes) => {
+            let mut hasher = cache::CacheKeyHasher::new();
+            hasher.write(&font_bytes);
+            hasher.finish()
+        }
+        Err(_) => {
+            return Err(Box::new(AppError::CouldNotOpenFontFile(opt.input_path().to_path_buf())));
+        }
+    };
+
+    let output_key = output_cache_key(opt, font_key);
+    let files = output_files(opt);
+    match cache.restore_output(output_key, &files) {
+        Ok(true) => {
+            println!("Restored the output from the cache in {}.", cache_dir.display());
+            return Ok(());
+        }
+        Ok(false) => {}
+        Err(_) => return Err(Box::new(AppError::CouldNotUseCache(cache_dir.clone()))),
+    }
+
+    generate_atlases(opt, Some(&cache), font_key)?;
+
+    if cache.store_output(output_key, &files).is_err() {
+        return Err(Box::new(AppError::CouldNotUseCache(cache_dir.clone())));
     }
 
     Ok(())
@@ -522,6 +2417,20 @@ fn run_app(opt: &Opt) -> Result<(), Box<dyn std::error::Error>> {
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opt = Opt::from_args();
-    verify_opt(&opt)?;
-    run_app(&opt)
+    match opt.command {
+        Some(Command::Inspect(ref inspect_opt)) => inspect::run_inspect(inspect_opt),
+        Some(Command::Unpack(ref unpack_opt)) => unpack::run_unpack(unpack_opt),
+        Some(Command::Pack(ref pack_opt)) => unpack::run_pack(pack_opt),
+        Some(Command::Preview(ref preview_opt)) => preview::run_preview(preview_opt),
+        Some(Command::Strikes(ref strikes_opt)) => strikes::run_strikes(strikes_opt),
+        Some(Command::Watch(ref watch_opt)) => {
+            verify_opt(&opt)?;
+            watch::run_watch(&opt, watch_opt)
+        }
+        None => {
+            verify_opt(&opt)?;
+            verify_output_paths(&opt)?;
+            run_app(&opt)
+        }
+    }
 }
@@ -0,0 +1,153 @@
+use crate::layout::AtlasLayout;
+
+
+/// A single downsampled level of an atlas image, each one half the size of the level above
+/// it, rounded up. The rows are in the same order as the rows of the atlas image, following
+/// the origin of the atlas.
+pub struct MipLevel {
+    pub data: Vec<u8>,
+}
+
+/// The part of the atlas image owned by a single glyph: its rectangle together with the
+/// extrusion around it. The region spans `x0..x1` and `y0..y1` in the top left chart.
+#[derive(Copy, Clone, Debug)]
+struct GlyphRegion {
+    code_point: usize,
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
+impl GlyphRegion {
+    /// The region covered by this region in a mip level with the given scale. A texel
+    /// belongs to the region when any part of it does.
+    fn scaled(&self, scale: usize) -> GlyphRegion {
+        GlyphRegion {
+            code_point: self.code_point,
+            x0: self.x0 / scale,
+            y0: self.y0 / scale,
+            x1: (self.x1 + scale - 1) / scale,
+            y1: (self.y1 + scale - 1) / scale,
+        }
+    }
+
+    fn overlaps(&self, other: &GlyphRegion) -> bool {
+        self.x0 < other.x1 && other.x0 < self.x1 && self.y0 < other.y1 && other.y0 < self.y1
+    }
+}
+
+/// Two glyphs that end up sharing texels in a mip level.
+#[derive(Copy, Clone, Debug)]
+pub struct MipLevelOverlap {
+    pub level: usize,
+    pub code_point: usize,
+    pub other_code_point: usize,
+}
+
+/// The region owned by each glyph with an image of its own in the atlas.
+fn glyph_regions(layout: &AtlasLayout) -> Vec<GlyphRegion> {
+    let spec = layout.spec;
+    layout.placements.iter().filter_map(|placement| {
+        if placement.shares_image_with.is_some() {
+            return None;
+        }
+        let width = usize::min(placement.bounds.width + spec.padding, placement.cell_width);
+        let height = usize::min(placement.bounds.height + spec.padding, placement.cell_height);
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        Some(GlyphRegion {
+            code_point: placement.code_point,
+            x0: placement.x.saturating_sub(spec.extrude),
+            y0: placement.y.saturating_sub(spec.extrude),
+            x1: usize::min(placement.x + width + spec.extrude, spec.width),
+            y1: usize::min(placement.y + height + spec.extrude, spec.height),
+        })
+    }).collect()
+}
+
+/// Check that no two glyphs share a texel in any of the first `levels` mip levels. Once two
+/// glyphs share a texel, no filter can keep them apart.
+fn check_regions(regions: &[GlyphRegion], levels: usize) -> Result<(), MipLevelOverlap> {
+    for level in 1..(levels + 1) {
+        let scaled: Vec<GlyphRegion> = regions.iter().map(|region| region.scaled(1 << level)).collect();
+        for (i, region) in scaled.iter().enumerate() {
+            for other in scaled[(i + 1)..].iter() {
+                if region.overlaps(other) {
+                    return Err(MipLevelOverlap {
+                        level: level,
+                        code_point: region.code_point,
+                        other_code_point: other.code_point,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Generate a mip chain of `levels` levels below the atlas image. Instead of filtering the
+/// whole image at once, which blends glyphs sitting next to each other, each level is built
+/// by box filtering every glyph region on its own, with everything outside of the region
+/// treated as transparent. This fails when the glyphs are too close together to keep apart
+/// at the smallest level.
+pub fn create_mip_chain(
+    layout: &AtlasLayout, image: &bmfa::BitmapFontAtlasImage, levels: usize) -> Result<Vec<MipLevel>, MipLevelOverlap> {
+
+    let regions = glyph_regions(layout);
+    check_regions(&regions, levels)?;
+
+    let width = image.width;
+    let height = image.height;
+    // Read the atlas image in the top left chart.
+    let base_pixel = |x: usize, y: usize, channel: usize| -> u32 {
+        let row = match image.origin {
+            bmfa::Origin::BottomLeft => height - y - 1,
+            bmfa::Origin::TopLeft => y,
+        };
+
+        image.data[4 * (row * width + x) + channel] as u32
+    };
+
+    let mut chain = Vec::with_capacity(levels);
+    for level in 1..(levels + 1) {
+        let scale = 1 << level;
+        let level_width = usize::max(1, (width + scale - 1) / scale);
+        let level_height = usize::max(1, (height + scale - 1) / scale);
+        let mut data = vec![0 as u8; 4 * level_width * level_height];
+        let area = (scale * scale) as u32;
+        for region in regions.iter() {
+            let scaled = region.scaled(scale);
+            for y in scaled.y0..scaled.y1 {
+                for x in scaled.x0..scaled.x1 {
+                    let src_x0 = usize::max(x * scale, region.x0);
+                    let src_y0 = usize::max(y * scale, region.y0);
+                    let src_x1 = usize::min((x + 1) * scale, region.x1);
+                    let src_y1 = usize::min((y + 1) * scale, region.y1);
+                    let row = match image.origin {
+                        bmfa::Origin::BottomLeft => level_height - y - 1,
+                        bmfa::Origin::TopLeft => y,
+                    };
+                    for channel in 0..4 {
+                        let mut sum = 0;
+                        for src_y in src_y0..src_y1 {
+                            for src_x in src_x0..src_x1 {
+                                sum += base_pixel(src_x, src_y, channel);
+                            }
+                        }
+                        data[4 * (row * level_width + x) + channel] = ((sum + area / 2) / area) as u8;
+                    }
+                }
+            }
+        }
+
+        chain.push(MipLevel {
+            data: data,
+        });
+    }
+
+    Ok(chain)
+}
@@ -0,0 +1,234 @@
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use crate::{AppError, GlyphMetrics, OptError};
+
+
+/// The order of the pixels inside each byte of a packed monochrome bitmap.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BitOrder {
+    /// The first pixel goes into the most significant bit.
+    MsbFirst,
+    /// The first pixel goes into the least significant bit.
+    LsbFirst,
+}
+
+impl BitOrder {
+    /// The name of the bit order as it is written on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            BitOrder::MsbFirst => "msb-first",
+            BitOrder::LsbFirst => "lsb-first",
+        }
+    }
+}
+
+pub fn parse_bit_order(st: &str) -> Result<BitOrder, OptError> {
+    match st {
+        "msb-first" => Ok(BitOrder::MsbFirst),
+        "lsb-first" => Ok(BitOrder::LsbFirst),
+        _ => Err(OptError::InvalidBitOrder(format!("{}", st))),
+    }
+}
+
+/// The way the pixels of a packed monochrome bitmap are grouped into bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PageLayout {
+    /// Each byte holds eight neighbouring pixels of a row, and each row starts on a new byte.
+    Rows,
+    /// The bitmap is cut into pages of eight rows, and each byte holds one column of a page,
+    /// the way SSD1306 style displays lay out their memory.
+    Columns,
+}
+
+impl PageLayout {
+    /// The name of the page layout as it is written on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            PageLayout::Rows => "rows",
+            PageLayout::Columns => "columns",
+        }
+    }
+}
+
+pub fn parse_page_layout(st: &str) -> Result<PageLayout, OptError> {
+    match st {
+        "rows" => Ok(PageLayout::Rows),
+        "columns" => Ok(PageLayout::Columns),
+        _ => Err(OptError::InvalidPageLayout(format!("{}", st))),
+    }
+}
+
+/// How the glyph bitmaps of a monochrome atlas get packed into bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MonoPacking {
+    pub bit_order: BitOrder,
+    pub page_layout: PageLayout,
+}
+
+impl MonoPacking {
+    /// The number of bytes a packed bitmap of the given size takes up.
+    pub fn packed_size(&self, width: usize, height: usize) -> usize {
+        match self.page_layout {
+            PageLayout::Rows => height * ((width + 7) / 8),
+            PageLayout::Columns => width * ((height + 7) / 8),
+        }
+    }
+
+    /// A sentence describing the packing, for the documentation of generated code.
+    pub fn description(&self) -> &'static str {
+        match (self.page_layout, self.bit_order) {
+            (PageLayout::Rows, BitOrder::MsbFirst) => {
+                "Each bitmap is packed row by row, eight pixels to a byte with the leftmost \
+                pixel in the most significant bit, and each row starts on a new byte."
+            }
+            (PageLayout::Rows, BitOrder::LsbFirst) => {
+                "Each bitmap is packed row by row, eight pixels to a byte with the leftmost \
+                pixel in the least significant bit, and each row starts on a new byte."
+            }
+            (PageLayout::Columns, BitOrder::MsbFirst) => {
+                "Each bitmap is packed in pages of eight rows, one byte per column in each page \
+                with the top pixel in the most significant bit."
+            }
+            (PageLayout::Columns, BitOrder::LsbFirst) => {
+                "Each bitmap is packed in pages of eight rows, one byte per column in each page \
+                with the top pixel in the least significant bit."
+            }
+        }
+    }
+
+    /// The bit a pixel goes into, counting from the first pixel of its byte.
+    fn bit(&self, index: usize) -> u8 {
+        match self.bit_order {
+            BitOrder::MsbFirst => 0x80 >> index,
+            BitOrder::LsbFirst => 0x01 << index,
+        }
+    }
+
+    /// Pack a rectangle of a coverage image, with one byte per pixel and the rows running from
+    /// the top down, into a monochrome bitmap. Pixels with any coverage are set.
+    pub fn pack(&self, pixels: &[u8], image_width: usize, x: usize, y: usize, width: usize, height: usize) -> Vec<u8> {
+        let mut packed = vec![0 as u8; self.packed_size(width, height)];
+        let stride = (width + 7) / 8;
+        for row in 0..height {
+            for column in 0..width {
+                if pixels[(y + row) * image_width + x + column] == 0 {
+                    continue;
+                }
+                match self.page_layout {
+                    PageLayout::Rows => {
+                        packed[row * stride + column / 8] |= self.bit(column % 8);
+                    }
+                    PageLayout::Columns => {
+                        packed[(row / 8) * width + column] |= self.bit(row % 8);
+                    }
+                }
+            }
+        }
+
+        packed
+    }
+}
+
+/// The packed bitmaps of every glyph rectangle in an atlas, one after another.
+pub struct PackedBitmaps {
+    /// The offset of the bitmap of each glyph in `data`, keyed by code point. Glyphs sharing
+    /// a rectangle in the atlas share a bitmap too.
+    pub offsets: HashMap<usize, usize>,
+    pub data: Vec<u8>,
+}
+
+/// Pack the bitmap of each glyph rectangle. The rectangles are given by code point as
+/// `(x, y, width, height)` in a coverage image with the rows running from the top down.
+pub fn pack_bitmaps<I>(packing: MonoPacking, pixels: &[u8], image_width: usize, rects: I) -> PackedBitmaps
+    where I: Iterator<Item = (usize, (usize, usize, usize, usize))>
+{
+    let mut offsets = HashMap::new();
+    let mut packed_rects = HashMap::new();
+    let mut data = vec![];
+    for (code_point, rect) in rects {
+        let offset = *packed_rects.entry(rect).or_insert_with(|| {
+            let (x, y, width, height) = rect;
+            let offset = data.len();
+            data.extend(packing.pack(pixels, image_width, x, y, width, height));
+            offset
+        });
+        offsets.insert(code_point, offset);
+    }
+
+    PackedBitmaps {
+        offsets: offsets,
+        data: data,
+    }
+}
+
+/// Pack the bitmap of every glyph rectangle of a monochrome atlas image.
+pub fn pack_atlas(
+    packing: MonoPacking,
+    image: &bmfa::BitmapFontAtlasImage, glyphs: &BTreeMap<usize, GlyphMetrics>) -> PackedBitmaps {
+
+    let pixels = crate::codegen::coverage_pixels(image);
+    let rects = glyphs.iter().map(|(&code_point, glyph)| {
+        (code_point, (glyph.x, glyph.y, glyph.width, glyph.height))
+    });
+
+    pack_bitmaps(packing, &pixels, image.width, rects)
+}
+
+/// Where the packed bitmap of a glyph sits in the packed bitmap file.
+#[derive(Clone, Debug, Serialize)]
+struct PackedGlyph {
+    /// The offset of the packed bitmap in bytes from the start of the file.
+    offset: usize,
+    /// The width of the bitmap in pixels.
+    width: usize,
+    /// The height of the bitmap in pixels.
+    height: usize,
+}
+
+/// The table of the packed bitmaps of a monochrome atlas, describing the packed bitmap file.
+#[derive(Clone, Debug, Serialize)]
+struct PackedBitmapTable {
+    /// The file name of the packed bitmap file, relative to the table.
+    bitmap_file: String,
+    bit_order: &'static str,
+    page_layout: &'static str,
+    /// The packed bitmap of each glyph, keyed by code point.
+    glyphs: BTreeMap<usize, PackedGlyph>,
+}
+
+/// Write the packed bitmaps of a monochrome atlas one after another to a binary file, and
+/// the offset and size of the bitmap of each glyph to a JSON table.
+pub fn write_packed_bitmaps(
+    bitmap_path: &Path, table_path: &Path,
+    packing: MonoPacking, bitmaps: &PackedBitmaps, glyphs: &BTreeMap<usize, GlyphMetrics>) -> Result<(), AppError> {
+
+    if fs::write(bitmap_path, &bitmaps.data).is_err() {
+        return Err(AppError::CouldNotCreateAtlasFile(bitmap_path.to_path_buf()));
+    }
+
+    let table = PackedBitmapTable {
+        bitmap_file: bitmap_path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        bit_order: packing.bit_order.name(),
+        page_layout: packing.page_layout.name(),
+        glyphs: glyphs.iter().map(|(&code_point, glyph)| {
+            let packed_glyph = PackedGlyph {
+                offset: bitmaps.offsets[&code_point],
+                width: glyph.width,
+                height: glyph.height,
+            };
+            (code_point, packed_glyph)
+        }).collect(),
+    };
+    let file = match File::create(table_path) {
+        Ok(val) => val,
+        Err(_) => return Err(AppError::CouldNotCreateAtlasFile(table_path.to_path_buf())),
+    };
+    if serde_json::to_writer_pretty(file, &table).is_err() {
+        return Err(AppError::CouldNotCreateAtlasFile(table_path.to_path_buf()));
+    }
+
+    Ok(())
+}
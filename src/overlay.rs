@@ -0,0 +1,106 @@
+use bmfa::BitmapFontAtlas;
+use crate::canvas::{Canvas, Color};
+use crate::preview::{self, GlyphRect};
+use crate::layout::AtlasLayout;
+use crate::{GlyphTable, OrderedAtlasMetadata};
+
+
+/// The color behind the atlas image, so that transparent regions stand out.
+const BACKGROUND_COLOR: Color = Color::new(32, 32, 32, 255);
+/// The color of the boundary of each glyph slot.
+const SLOT_COLOR: Color = Color::new(128, 128, 128, 255);
+/// The color of the padding region inside each glyph slot.
+const PADDING_COLOR: Color = Color::new(0, 96, 255, 64);
+/// The color of the glyph rectangle recorded in the atlas metadata.
+const GLYPH_RECT_COLOR: Color = Color::new(0, 255, 0, 255);
+/// The color of the baseline of each glyph.
+const BASELINE_COLOR: Color = Color::new(255, 0, 0, 255);
+/// The color of the code point label of each glyph.
+const LABEL_COLOR: Color = Color::new(255, 255, 0, 255);
+
+/// Draw a diagnostic copy of an atlas image. The slot boundaries, the padding regions, and
+/// the baselines come from the sampled glyph table and the atlas layout, while the glyph
+/// rectangles come from the atlas metadata, so any disagreement between where a glyph was
+/// placed and where the metadata says it is shows up in the image. Each glyph is labelled
+/// with its code point in hexadecimal. In a trimmed atlas, the boundary of every packed glyph
+/// rectangle is drawn in place of the grid slots. The overlay is always drawn upright.
+pub fn create_debug_overlay(glyph_tab: &GlyphTable, layout: &AtlasLayout, atlas: &BitmapFontAtlas) -> Canvas {
+    let spec = layout.spec;
+    let mut canvas = Canvas::new(spec.width, spec.height, BACKGROUND_COLOR);
+    for y in 0..spec.height {
+        for x in 0..spec.width {
+            let color = preview::atlas_pixel(atlas, x, y);
+            canvas.blend_pixel(x as i64, y as i64, color);
+        }
+    }
+
+    let half_padding = spec.padding / 2;
+    if layout.trimmed {
+        for placement in layout.placements.iter() {
+            draw_cell(
+                &mut canvas, placement.x as i64, placement.y as i64,
+                placement.cell_width, placement.cell_height,
+                half_padding, placement.bounds.width, placement.bounds.height
+            );
+        }
+    } else {
+        let slot = spec.slot_glyph_size;
+        for row in 0..spec.rows {
+            for col in 0..spec.columns {
+                let (slot_x, slot_y) = spec.slot_origin(col, row);
+                draw_cell(
+                    &mut canvas, slot_x as i64, slot_y as i64, slot, slot,
+                    half_padding, spec.glyph_size, spec.glyph_size
+                );
+            }
+        }
+    }
+
+    let glyph_metadata = OrderedAtlasMetadata::from(&atlas.metadata).glyph_metadata;
+    for (code_point, glyph) in glyph_metadata.iter() {
+        let rect = GlyphRect::from_metadata(atlas, glyph);
+        canvas.draw_rectangle(rect.x as i64, rect.y as i64, rect.width, rect.height, GLYPH_RECT_COLOR);
+
+        let placement = layout.placements
+            .binary_search_by_key(code_point, |placement| placement.code_point)
+            .ok()
+            .map(|index| &layout.placements[index]);
+        if let Some(placement) = placement {
+            // The glyph image sits `padding / 2` pixels into its rectangle, and the baseline
+            // lies as far below the top of the image as the image reaches above it.
+            let image_top = (placement.y + half_padding) as i64;
+            let image_height = placement.bounds.height as i64;
+            let baseline = image_top + image_height + crate::placed_glyph_bottom(glyph_tab, placement);
+            canvas.draw_horizontal_line(placement.x as i64, baseline, placement.cell_width, BASELINE_COLOR);
+        }
+
+        let label = format!("{:X}", code_point);
+        if !layout.trimmed {
+            let order = code_point - 32;
+            let (slot_x, slot_y) = spec.slot_origin(order % spec.columns, order / spec.columns);
+            canvas.draw_label(slot_x as i64 + 1, slot_y as i64 + 1, &label, LABEL_COLOR);
+        } else if let Some(placement) = placement.filter(|p| p.shares_image_with.is_none()) {
+            canvas.draw_label(placement.x as i64 + 1, placement.y as i64 + 1, &label, LABEL_COLOR);
+        }
+    }
+
+    canvas
+}
+
+/// Draw the boundary of the region of the atlas reserved for one glyph, along with its
+/// padding. The glyph area sits `half_padding` pixels into the region, and everything
+/// else in the region is padding.
+fn draw_cell(
+    canvas: &mut Canvas, x: i64, y: i64, width: usize, height: usize,
+    half_padding: usize, glyph_width: usize, glyph_height: usize) {
+
+    let inner = half_padding as i64;
+    let far_x = (half_padding + glyph_width) as i64;
+    let far_y = (half_padding + glyph_height) as i64;
+    canvas.fill_rectangle(x, y, width, half_padding, PADDING_COLOR);
+    canvas.fill_rectangle(x, y + far_y, width, height - (half_padding + glyph_height), PADDING_COLOR);
+    canvas.fill_rectangle(x, y + inner, half_padding, glyph_height, PADDING_COLOR);
+    canvas.fill_rectangle(x + far_x, y + inner, width - (half_padding + glyph_width), glyph_height, PADDING_COLOR);
+
+    canvas.draw_rectangle(x, y, width, height, SLOT_COLOR);
+}
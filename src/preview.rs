@@ -0,0 +1,240 @@
+use bmfa::{BitmapFontAtlas, GlyphMetadata};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use crate::canvas::{Canvas, Color};
+use crate::{AppError, AtlasMetrics};
+
+
+/// The background color of a preview image.
+const BACKGROUND_COLOR: Color = Color::new(0, 0, 0, 255);
+/// The color of the baseline of each line of text in the preview overlay.
+const BASELINE_COLOR: Color = Color::new(255, 0, 0, 255);
+/// The color of the bounding box of each glyph in the preview overlay.
+const GLYPH_BOX_COLOR: Color = Color::new(0, 255, 0, 255);
+
+/// The shell input options for `fontgen preview`.
+#[derive(Debug, StructOpt)]
+pub struct PreviewOpt {
+    /// The path to the bitmap font atlas file to render the text with.
+    #[structopt(parse(from_os_str))]
+    #[structopt(long = "atlas")]
+    pub atlas_path: PathBuf,
+    /// The text to render. Line breaks in the text start a new line.
+    #[structopt(long = "text")]
+    pub text: String,
+    /// The path to the output PNG image.
+    #[structopt(parse(from_os_str))]
+    #[structopt(long = "out")]
+    pub output_path: PathBuf,
+    /// Draw the baseline of each line and the bounding box of each glyph over the text.
+    #[structopt(long = "overlay")]
+    pub overlay: bool,
+}
+
+/// The pixel rectangle of a glyph in the atlas image, with the origin at the top left corner.
+#[derive(Copy, Clone, Debug)]
+pub struct GlyphRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl GlyphRect {
+    /// Compute the pixel rectangle of a glyph from its metadata. The glyph offsets are
+    /// fractions of the atlas dimensions, and the glyph dimensions are fractions of
+    /// the slot glyph size.
+    pub fn from_metadata(atlas: &BitmapFontAtlas, glyph: &GlyphMetadata) -> GlyphRect {
+        let metadata = &atlas.metadata;
+        let slot_glyph_size = metadata.slot_glyph_size as f32;
+
+        GlyphRect {
+            x: (glyph.x_min * metadata.width as f32).round() as usize,
+            y: (glyph.y_min * metadata.height as f32).round() as usize,
+            width: (glyph.width * slot_glyph_size).round() as usize,
+            height: (glyph.height * slot_glyph_size).round() as usize,
+        }
+    }
+}
+
+/// Load the metrics written next to an atlas file with trimmed glyphs. An atlas without
+/// a metrics file has no metrics.
+pub fn load_atlas_metrics(atlas_path: &Path) -> Result<Option<AtlasMetrics>, AppError> {
+    let metrics_path = crate::atlas_metrics_path(atlas_path);
+    if !metrics_path.exists() {
+        return Ok(None);
+    }
+
+    match AtlasMetrics::load(&metrics_path) {
+        Ok(metrics) => Ok(Some(metrics)),
+        Err(_) => Err(AppError::CouldNotOpenAtlasFile(metrics_path)),
+    }
+}
+
+/// Fetch the pixel at `(x, y)` in the top left coordinate chart from an atlas image,
+/// whatever the origin of the atlas is.
+pub fn atlas_pixel(atlas: &BitmapFontAtlas, x: usize, y: usize) -> Color {
+    let image = &atlas.image;
+    if x >= image.width || y >= image.height {
+        return Color::new(0, 0, 0, 0);
+    }
+
+    let row = match image.origin {
+        bmfa::Origin::BottomLeft => image.height - y - 1,
+        bmfa::Origin::TopLeft => y,
+    };
+    let index = 4 * (row * image.width + x);
+
+    Color::new(image.data[index], image.data[index + 1], image.data[index + 2], image.data[index + 3])
+}
+
+/// A single glyph placed in the preview image.
+struct PlacedGlyph {
+    /// The horizontal position of the left edge of the glyph in pixels.
+    x: i64,
+    /// The vertical position of the top edge of the glyph in pixels.
+    y: i64,
+    rect: GlyphRect,
+}
+
+/// The placement of every glyph in a block of text, relative to the baseline of the first line.
+struct TextLayout {
+    glyphs: Vec<PlacedGlyph>,
+    /// The vertical position of the baseline of each line.
+    baselines: Vec<i64>,
+    min_x: i64,
+    max_x: i64,
+    min_y: i64,
+    max_y: i64,
+}
+
+/// Lay out a block of text. Without atlas metrics, the layout uses only the atlas metadata:
+/// the pen advances by the width of each glyph rectangle, and each glyph is shifted off of
+/// the baseline by its `y_offset`. A glyph with metrics is placed at its exact offset from the
+/// pen position instead, and the pen moves on by its advance. Code points missing from
+/// the atlas advance the pen like a space.
+fn layout_text(atlas: &BitmapFontAtlas, metrics: Option<&AtlasMetrics>, text: &str) -> TextLayout {
+    let slot_glyph_size = atlas.metadata.slot_glyph_size as f32;
+    let line_height = atlas.metadata.slot_glyph_size as i64;
+    let mut glyphs = vec![];
+    let mut baselines = vec![];
+    let mut min_x = 0;
+    let mut max_x = 0;
+    let mut min_y = -line_height;
+    let mut max_y = 0;
+    for (line_number, line) in text.lines().enumerate() {
+        let baseline = line_number as i64 * line_height;
+        baselines.push(baseline);
+        min_y = i64::min(min_y, baseline - line_height);
+        max_y = i64::max(max_y, baseline);
+        let mut pen_x = 0;
+        for ch in line.chars() {
+            if let Some(glyph) = metrics.and_then(|metrics| metrics.glyphs.get(&(ch as usize))) {
+                let rect = GlyphRect {
+                    x: glyph.x,
+                    y: glyph.y,
+                    width: glyph.width,
+                    height: glyph.height,
+                };
+                let left = pen_x + glyph.x_offset;
+                let top = baseline - glyph.y_offset;
+                glyphs.push(PlacedGlyph { x: left, y: top, rect: rect });
+
+                min_x = i64::min(min_x, left);
+                max_x = i64::max(max_x, left + rect.width as i64);
+                min_y = i64::min(min_y, top);
+                max_y = i64::max(max_y, top + rect.height as i64);
+                pen_x += glyph.advance;
+                continue;
+            }
+
+            let glyph = match atlas.metadata.glyph_metadata.get(&(ch as usize)) {
+                Some(glyph) => glyph,
+                None => {
+                    pen_x += (0.5 * slot_glyph_size).round() as i64;
+                    continue;
+                }
+            };
+
+            let rect = GlyphRect::from_metadata(atlas, glyph);
+            let y_offset = (glyph.y_offset * slot_glyph_size).round() as i64;
+            let bottom = baseline - y_offset;
+            let top = bottom - rect.height as i64;
+            glyphs.push(PlacedGlyph { x: pen_x, y: top, rect: rect });
+
+            min_x = i64::min(min_x, pen_x);
+            max_x = i64::max(max_x, pen_x + rect.width as i64);
+            min_y = i64::min(min_y, top);
+            max_y = i64::max(max_y, bottom);
+            pen_x += rect.width as i64;
+        }
+        max_x = i64::max(max_x, pen_x);
+    }
+
+    TextLayout {
+        glyphs: glyphs,
+        baselines: baselines,
+        min_x: min_x,
+        max_x: max_x,
+        min_y: min_y,
+        max_y: max_y,
+    }
+}
+
+/// Render a block of text into a canvas using the glyph images in the atlas.
+pub fn render_text(
+    atlas: &BitmapFontAtlas, metrics: Option<&AtlasMetrics>, text: &str, overlay: bool) -> Canvas {
+
+    let layout = layout_text(atlas, metrics, text);
+    let margin = usize::max(1, atlas.metadata.slot_glyph_size / 4) as i64;
+    let width = (layout.max_x - layout.min_x + 2 * margin) as usize;
+    let height = (layout.max_y - layout.min_y + 2 * margin) as usize;
+    let offset_x = margin - layout.min_x;
+    let offset_y = margin - layout.min_y;
+    let mut canvas = Canvas::new(width, height, BACKGROUND_COLOR);
+
+    for glyph in layout.glyphs.iter() {
+        for y in 0..glyph.rect.height {
+            for x in 0..glyph.rect.width {
+                let color = atlas_pixel(atlas, glyph.rect.x + x, glyph.rect.y + y);
+                canvas.blend_pixel(offset_x + glyph.x + x as i64, offset_y + glyph.y + y as i64, color);
+            }
+        }
+    }
+
+    if overlay {
+        for baseline in layout.baselines.iter() {
+            canvas.draw_horizontal_line(0, offset_y + baseline, width, BASELINE_COLOR);
+        }
+        for glyph in layout.glyphs.iter() {
+            canvas.draw_rectangle(
+                offset_x + glyph.x, offset_y + glyph.y,
+                glyph.rect.width, glyph.rect.height, GLYPH_BOX_COLOR
+            );
+        }
+    }
+
+    canvas
+}
+
+/// Run the `preview` subcommand.
+pub fn run_preview(opt: &PreviewOpt) -> Result<(), Box<dyn std::error::Error>> {
+    if opt.output_path.exists() {
+        return Err(Box::new(crate::OptError::OutputFileExists(opt.output_path.clone())));
+    }
+
+    let atlas = match bmfa::load(&opt.atlas_path) {
+        Ok(val) => val,
+        Err(_) => {
+            return Err(Box::new(AppError::CouldNotOpenAtlasFile(opt.atlas_path.clone())));
+        }
+    };
+
+    let metrics = load_atlas_metrics(&opt.atlas_path)?;
+    let canvas = render_text(&atlas, metrics.as_ref(), &opt.text, opt.overlay);
+    if canvas.save(&opt.output_path).is_err() {
+        return Err(Box::new(AppError::CouldNotCreateAtlasFile(opt.output_path.clone())));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use freetype::Library;
+use std::path::PathBuf;
+use structopt::StructOpt;
+use crate::AppError;
+
+
+/// The shell input options for `fontgen strikes`.
+#[derive(Debug, StructOpt)]
+pub struct StrikesOpt {
+    /// The path to the font file to list the bitmap strikes of.
+    #[structopt(parse(from_os_str))]
+    pub font_path: PathBuf,
+}
+
+/// A set of hand drawn glyph bitmaps in a font, all drawn at the same size. Bitmap fonts like
+/// BDF and PCF fonts only have strikes, and TrueType and OpenType fonts can embed them next
+/// to their outlines.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Strike {
+    /// The index of the strike in the font.
+    pub index: usize,
+    /// The size of the em square of the strike in pixels.
+    pub pixel_size: usize,
+    /// The nominal width of the glyphs in the strike in pixels.
+    pub width: usize,
+    /// The nominal height of the glyphs in the strike in pixels.
+    pub height: usize,
+}
+
+/// List the bitmap strikes of a typeface, in the order the font stores them.
+pub fn font_strikes(face: &freetype::face::Face) -> Vec<Strike> {
+    let raw = face.raw();
+    let count = raw.num_fixed_sizes.max(0) as usize;
+    if count == 0 || raw.available_sizes.is_null() {
+        return vec![];
+    }
+
+    // FreeType keeps one entry per strike in the face for as long as the face is open.
+    let sizes = unsafe { std::slice::from_raw_parts(raw.available_sizes, count) };
+    sizes.iter().enumerate().map(|(index, size)| {
+        Strike {
+            index: index,
+            // The size is in 26.6 fixed point.
+            pixel_size: ((size.y_ppem + 32) >> 6) as usize,
+            width: size.width.max(0) as usize,
+            height: size.height.max(0) as usize,
+        }
+    }).collect()
+}
+
+/// Choose the strike to sample glyphs from. This is the strike drawn at `pixel_size` when one
+/// is requested, and otherwise the largest strike that fits into `glyph_size`.
+pub fn choose_strike(strikes: &[Strike], pixel_size: Option<usize>, glyph_size: usize) -> Option<Strike> {
+    match pixel_size {
+        Some(pixel_size) => strikes.iter().find(|strike| strike.pixel_size == pixel_size).cloned(),
+        None => strikes.iter()
+            .filter(|strike| strike.pixel_size <= glyph_size)
+            .max_by_key(|strike| strike.pixel_size)
+            .cloned(),
+    }
+}
+
+/// Choose the strike to scale glyphs from. This is the strike drawn at `pixel_size` when one
+/// is requested, and otherwise the smallest strike at least `glyph_size` pixels large, so that
+/// glyphs get scaled down, or the largest strike when every strike is smaller.
+pub fn choose_strike_to_scale(strikes: &[Strike], pixel_size: Option<usize>, glyph_size: usize) -> Option<Strike> {
+    match pixel_size {
+        Some(_) => choose_strike(strikes, pixel_size, glyph_size),
+        None => strikes.iter()
+            .filter(|strike| strike.pixel_size >= glyph_size)
+            .min_by_key(|strike| strike.pixel_size)
+            .or_else(|| strikes.iter().max_by_key(|strike| strike.pixel_size))
+            .cloned(),
+    }
+}
+
+/// Select a strike of a typeface, so that glyphs get loaded from its bitmaps at their
+/// original size instead of being rasterized from outlines.
+pub fn select_strike(face: &freetype::face::Face, strike: Strike) -> Result<(), freetype::error::Error> {
+    // The face handle is owned by `face`, and selecting a strike only changes its active size.
+    let error = unsafe {
+        freetype::ffi::FT_Select_Size(
+            face.raw() as *const freetype::ffi::FT_FaceRec as freetype::ffi::FT_Face,
+            strike.index as freetype::ffi::FT_Int
+        )
+    };
+    if error == 0 {
+        Ok(())
+    } else {
+        Err(freetype::error::Error::from(error))
+    }
+}
+
+/// The pixel sizes of a list of strikes as a comma separated list.
+pub fn strike_sizes(strikes: &[Strike]) -> String {
+    let sizes: Vec<String> = strikes.iter().map(|strike| strike.pixel_size.to_string()).collect();
+
+    sizes.join(", ")
+}
+
+/// Run the `strikes` subcommand.
+pub fn run_strikes(opt: &StrikesOpt) -> Result<(), Box<dyn std::error::Error>> {
+    let ft = Library::init().expect("Failed to initialize FreeType library.");
+    let face = match ft.new_face(&opt.font_path, 0) {
+        Ok(val) => val,
+        Err(_) => return Err(Box::new(AppError::CouldNotOpenFontFile(opt.font_path.clone()))),
+    };
+
+    let strikes = font_strikes(&face);
+    if strikes.is_empty() {
+        println!("{} has no bitmap strikes.", opt.font_path.display());
+        return Ok(());
+    }
+    println!("{:>10} {:>6} {:>6}", "pixel size", "width", "height");
+    for strike in strikes.iter() {
+        println!("{:>10} {:>6} {:>6}", strike.pixel_size, strike.width, strike.height);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::mipmap::MipLevel;
+use crate::OptError;
+
+
+/// A GPU texture container the atlas image can be written to, next to the atlas file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// A Khronos KTX 2.0 texture, as loaded by Vulkan renderers.
+    Ktx2,
+    /// A DirectDraw Surface texture, as loaded by Direct3D renderers.
+    Dds,
+}
+
+impl ImageFormat {
+    /// The name of the format as it is written on the command line, which is also the
+    /// extension of the texture file.
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageFormat::Ktx2 => "ktx2",
+            ImageFormat::Dds => "dds",
+        }
+    }
+}
+
+pub fn parse_image_format(st: &str) -> Result<ImageFormat, OptError> {
+    match st {
+        "ktx2" => Ok(ImageFormat::Ktx2),
+        "dds" => Ok(ImageFormat::Dds),
+        _ => Err(OptError::InvalidImageFormat(format!("{}", st))),
+    }
+}
+
+/// The image data of each level of a texture, starting with the atlas image itself. The rows
+/// are in the order of the origin of the atlas.
+fn texture_levels<'a>(image: &'a bmfa::BitmapFontAtlasImage, mip_chain: &'a [MipLevel]) -> Vec<&'a [u8]> {
+    let mut levels: Vec<&[u8]> = vec![&image.data];
+    for mip_level in mip_chain.iter() {
+        levels.push(&mip_level.data);
+    }
+
+    levels
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Pad a byte buffer with zeros up to a multiple of `alignment` bytes.
+fn pad_to(bytes: &mut Vec<u8>, alignment: usize) {
+    while bytes.len() % alignment != 0 {
+        bytes.push(0);
+    }
+}
+
+/// The KTX 2.0 file identifier.
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+/// `VK_FORMAT_R8G8B8A8_UNORM`.
+const VK_FORMAT_R8G8B8A8_UNORM: u32 = 37;
+/// The size of the KTX 2.0 header and index, up to the level index.
+const KTX2_HEADER_SIZE: usize = 80;
+/// The size of each entry in the KTX 2.0 level index.
+const KTX2_LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+/// The alignment of the data of each level in a KTX 2.0 file, which is the least common
+/// multiple of the texel size and four.
+const KTX2_LEVEL_ALIGNMENT: usize = 4;
+
+/// The data format descriptor for unsigned normalized 8 bit RGBA pixels with linear transfer.
+fn ktx2_data_format_descriptor() -> Vec<u8> {
+    let samples = [0, 1, 2, 15];
+    let block_size = 24 + 16 * samples.len();
+    let mut dfd = vec![];
+    push_u32(&mut dfd, (4 + block_size) as u32);
+    // The vendor and the descriptor type of the basic descriptor block are both zero.
+    push_u32(&mut dfd, 0);
+    push_u32(&mut dfd, ((block_size as u32) << 16) | 2);
+    // The RGBSDA color model, BT.709 primaries, a linear transfer function, and straight alpha.
+    dfd.extend_from_slice(&[1, 1, 1, 0]);
+    // A single texel per block.
+    dfd.extend_from_slice(&[0, 0, 0, 0]);
+    // Four bytes per texel in the first plane.
+    dfd.extend_from_slice(&[4, 0, 0, 0, 0, 0, 0, 0]);
+    for (i, channel) in samples.iter().enumerate() {
+        // The bit offset, the bit length minus one, and the channel.
+        push_u32(&mut dfd, (8 * i as u32) | (7 << 16) | (channel << 24));
+        push_u32(&mut dfd, 0);
+        push_u32(&mut dfd, 0);
+        push_u32(&mut dfd, 255);
+    }
+
+    dfd
+}
+
+/// The key/value data of a KTX 2.0 file. The entries are sorted by key, as required.
+fn ktx2_key_value_data(origin: bmfa::Origin, key_values: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let orientation: &[u8] = match origin {
+        bmfa::Origin::BottomLeft => b"ru\0",
+        bmfa::Origin::TopLeft => b"rd\0",
+    };
+    let writer = format!("fontgen {}\0", env!("CARGO_PKG_VERSION"));
+    let mut entries: Vec<(&str, &[u8])> = vec![
+        ("KTXorientation", orientation),
+        ("KTXwriter", writer.as_bytes()),
+    ];
+    for &(key, ref value) in key_values.iter() {
+        entries.push((key, value));
+    }
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut kvd = vec![];
+    for (key, value) in entries.into_iter() {
+        push_u32(&mut kvd, (key.len() + 1 + value.len()) as u32);
+        kvd.extend_from_slice(key.as_bytes());
+        kvd.push(0);
+        kvd.extend_from_slice(value);
+        pad_to(&mut kvd, 4);
+    }
+
+    kvd
+}
+
+/// Encode the atlas image and its mip levels as a KTX 2.0 texture. The glyph metadata goes
+/// into the key/value data, and the `KTXorientation` key records the origin of the atlas.
+fn encode_ktx2(
+    image: &bmfa::BitmapFontAtlasImage, mip_chain: &[MipLevel], key_values: &[(&str, Vec<u8>)]) -> Vec<u8> {
+
+    let levels = texture_levels(image, mip_chain);
+    let dfd = ktx2_data_format_descriptor();
+    let kvd = ktx2_key_value_data(image.origin, key_values);
+    let dfd_offset = KTX2_HEADER_SIZE + KTX2_LEVEL_INDEX_ENTRY_SIZE * levels.len();
+    let kvd_offset = dfd_offset + dfd.len();
+
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&KTX2_IDENTIFIER);
+    push_u32(&mut bytes, VK_FORMAT_R8G8B8A8_UNORM);
+    // The type size of an 8 bit format.
+    push_u32(&mut bytes, 1);
+    push_u32(&mut bytes, image.width as u32);
+    push_u32(&mut bytes, image.height as u32);
+    // The depth, the number of array layers, the number of faces, the number of levels,
+    // and the supercompression scheme.
+    push_u32(&mut bytes, 0);
+    push_u32(&mut bytes, 0);
+    push_u32(&mut bytes, 1);
+    push_u32(&mut bytes, levels.len() as u32);
+    push_u32(&mut bytes, 0);
+    push_u32(&mut bytes, dfd_offset as u32);
+    push_u32(&mut bytes, dfd.len() as u32);
+    push_u32(&mut bytes, kvd_offset as u32);
+    push_u32(&mut bytes, kvd.len() as u32);
+    // There is no supercompression global data.
+    push_u64(&mut bytes, 0);
+    push_u64(&mut bytes, 0);
+
+    // The level data is stored from the smallest level to the largest, after the descriptor
+    // and the key/value data, with each level aligned to the four bytes of a texel.
+    let mut level_offsets = vec![0; levels.len()];
+    let mut offset = kvd_offset + kvd.len();
+    for (i, level) in levels.iter().enumerate().rev() {
+        offset += (KTX2_LEVEL_ALIGNMENT - offset % KTX2_LEVEL_ALIGNMENT) % KTX2_LEVEL_ALIGNMENT;
+        level_offsets[i] = offset;
+        offset += level.len();
+    }
+    for (i, level) in levels.iter().enumerate() {
+        push_u64(&mut bytes, level_offsets[i] as u64);
+        push_u64(&mut bytes, level.len() as u64);
+        push_u64(&mut bytes, level.len() as u64);
+    }
+
+    bytes.extend_from_slice(&dfd);
+    bytes.extend_from_slice(&kvd);
+    for level in levels.iter().rev() {
+        pad_to(&mut bytes, KTX2_LEVEL_ALIGNMENT);
+        bytes.extend_from_slice(level);
+    }
+
+    bytes
+}
+
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PITCH: u32 = 0x8;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x400000;
+
+/// Encode the atlas image and its mip levels as an uncompressed 32 bit RGBA DirectDraw Surface
+/// texture. The format has no way to record an origin, so the rows are written in the order
+/// the atlas stores them.
+fn encode_dds(image: &bmfa::BitmapFontAtlasImage, mip_chain: &[MipLevel]) -> Vec<u8> {
+    let levels = texture_levels(image, mip_chain);
+    let has_mipmaps = levels.len() > 1;
+
+    let mut bytes = vec![];
+    bytes.extend_from_slice(b"DDS ");
+    push_u32(&mut bytes, 124);
+    let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PITCH | DDSD_PIXELFORMAT;
+    if has_mipmaps {
+        flags |= DDSD_MIPMAPCOUNT;
+    }
+    push_u32(&mut bytes, flags);
+    push_u32(&mut bytes, image.height as u32);
+    push_u32(&mut bytes, image.width as u32);
+    push_u32(&mut bytes, 4 * image.width as u32);
+    // The depth.
+    push_u32(&mut bytes, 0);
+    push_u32(&mut bytes, levels.len() as u32);
+    for _ in 0..11 {
+        push_u32(&mut bytes, 0);
+    }
+
+    // The pixel format.
+    push_u32(&mut bytes, 32);
+    push_u32(&mut bytes, DDPF_RGB | DDPF_ALPHAPIXELS);
+    // The four character code.
+    push_u32(&mut bytes, 0);
+    push_u32(&mut bytes, 32);
+    push_u32(&mut bytes, 0x0000_00FF);
+    push_u32(&mut bytes, 0x0000_FF00);
+    push_u32(&mut bytes, 0x00FF_0000);
+    push_u32(&mut bytes, 0xFF00_0000);
+
+    let mut caps = DDSCAPS_TEXTURE;
+    if has_mipmaps {
+        caps |= DDSCAPS_COMPLEX | DDSCAPS_MIPMAP;
+    }
+    push_u32(&mut bytes, caps);
+    for _ in 0..4 {
+        push_u32(&mut bytes, 0);
+    }
+
+    for level in levels.iter() {
+        bytes.extend_from_slice(level);
+    }
+
+    bytes
+}
+
+/// Write the atlas image and its mip levels to a texture file. For KTX 2.0 textures, each
+/// key/value pair gets stored in the texture.
+pub fn write_texture(
+    path: &Path, format: ImageFormat,
+    image: &bmfa::BitmapFontAtlasImage, mip_chain: &[MipLevel],
+    key_values: &[(&str, Vec<u8>)]) -> io::Result<()> {
+
+    let bytes = match format {
+        ImageFormat::Ktx2 => encode_ktx2(image, mip_chain, key_values),
+        ImageFormat::Dds => encode_dds(image, mip_chain),
+    };
+
+    fs::write(path, bytes)
+}
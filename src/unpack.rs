@@ -0,0 +1,209 @@
+use bmfa::BitmapFontAtlas;
+use serde_derive::{Deserialize, Serialize};
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use crate::{AppError, OrderedAtlasMetadata};
+
+
+/// The shell input options for `fontgen unpack`.
+#[derive(Debug, StructOpt)]
+pub struct UnpackOpt {
+    /// The path to the bitmap font atlas file to unpack.
+    #[structopt(parse(from_os_str))]
+    pub atlas_path: PathBuf,
+    /// The directory to write the atlas image and metadata into.
+    #[structopt(parse(from_os_str))]
+    #[structopt(long = "out-dir")]
+    pub out_dir: PathBuf,
+}
+
+/// The shell input options for `fontgen pack`.
+#[derive(Debug, StructOpt)]
+pub struct PackOpt {
+    /// The path to the JSON metadata file written by `fontgen unpack`.
+    #[structopt(parse(from_os_str))]
+    pub metadata_path: PathBuf,
+    /// The path to the atlas image. This defaults to the image named in the metadata file.
+    #[structopt(parse(from_os_str))]
+    #[structopt(long = "image")]
+    pub image_path: Option<PathBuf>,
+    /// The path to the output atlas file.
+    #[structopt(parse(from_os_str))]
+    #[structopt(short = "o", long = "output")]
+    pub output_path: PathBuf,
+}
+
+/// A description of the atlas image written next to the metadata file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct UnpackedImage {
+    /// The file name of the image, relative to the metadata file.
+    file: String,
+    /// The width of the image in pixels.
+    width: usize,
+    /// The height of the image in pixels.
+    height: usize,
+    /// The origin of the atlas the image was unpacked from. The image itself is always
+    /// written upright, with the first row at the top.
+    origin: bmfa::Origin,
+}
+
+/// The contents of the metadata file written by `fontgen unpack`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct UnpackedAtlas {
+    image: UnpackedImage,
+    metadata: OrderedAtlasMetadata,
+}
+
+#[derive(Debug)]
+pub enum PackError {
+    CouldNotReadMetadataFile(PathBuf),
+    CouldNotReadImageFile(PathBuf),
+    ImageDimensionsMismatch(usize, usize, usize, usize),
+    OriginMismatch(bmfa::Origin, bmfa::Origin),
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PackError::CouldNotReadMetadataFile(ref path) => {
+                write!(f, "Could not read atlas metadata file: {}.", path.display())
+            }
+            PackError::CouldNotReadImageFile(ref path) => {
+                write!(f, "Could not read atlas image file: {}.", path.display())
+            }
+            PackError::ImageDimensionsMismatch(width, height, metadata_width, metadata_height) => {
+                write!(
+                    f,
+                    "The atlas image is {}x{} pixels, but the metadata describes \
+                    an atlas of {}x{} pixels.",
+                    width, height, metadata_width, metadata_height
+                )
+            }
+            PackError::OriginMismatch(image_origin, metadata_origin) => {
+                write!(
+                    f,
+                    "The atlas image was unpacked with origin {}, but the metadata has origin {}.",
+                    crate::origin_name(image_origin), crate::origin_name(metadata_origin)
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for PackError {}
+
+/// The file name of the unpacked atlas with the given extension.
+fn unpacked_file_name(atlas_path: &Path, extension: &str) -> String {
+    let stem = match atlas_path.file_stem() {
+        Some(stem) => stem.to_string_lossy().into_owned(),
+        None => String::from("atlas"),
+    };
+
+    format!("{}.{}", stem, extension)
+}
+
+/// Run the `unpack` subcommand.
+pub fn run_unpack(opt: &UnpackOpt) -> Result<(), Box<dyn std::error::Error>> {
+    let atlas = match bmfa::load(&opt.atlas_path) {
+        Ok(val) => val,
+        Err(_) => {
+            return Err(Box::new(AppError::CouldNotOpenAtlasFile(opt.atlas_path.clone())));
+        }
+    };
+
+    if std::fs::create_dir_all(&opt.out_dir).is_err() {
+        return Err(Box::new(AppError::CouldNotCreateAtlasFile(opt.out_dir.clone())));
+    }
+
+    // Write the image out upright so it can be edited directly.
+    let mut image_data = atlas.image.data.clone();
+    if atlas.image.origin == bmfa::Origin::BottomLeft {
+        crate::flip_vertically(&mut image_data, 4 * atlas.image.width, atlas.image.height);
+    }
+    let image_file_name = unpacked_file_name(&opt.atlas_path, "png");
+    let image_path = opt.out_dir.join(&image_file_name);
+    let image_result = image::save_buffer(
+        &image_path, &image_data,
+        atlas.image.width as u32, atlas.image.height as u32, image::ColorType::RGBA(8)
+    );
+    if image_result.is_err() {
+        return Err(Box::new(AppError::CouldNotCreateAtlasFile(image_path)));
+    }
+
+    let unpacked = UnpackedAtlas {
+        image: UnpackedImage {
+            file: image_file_name,
+            width: atlas.image.width,
+            height: atlas.image.height,
+            origin: atlas.image.origin,
+        },
+        metadata: OrderedAtlasMetadata::from(&atlas.metadata),
+    };
+    let metadata_path = opt.out_dir.join(unpacked_file_name(&opt.atlas_path, "json"));
+    let metadata_file = match File::create(&metadata_path) {
+        Ok(val) => val,
+        Err(_) => return Err(Box::new(AppError::CouldNotCreateAtlasFile(metadata_path))),
+    };
+    if serde_json::to_writer_pretty(metadata_file, &unpacked).is_err() {
+        return Err(Box::new(AppError::CouldNotCreateAtlasFile(metadata_path)));
+    }
+
+    Ok(())
+}
+
+/// Run the `pack` subcommand.
+pub fn run_pack(opt: &PackOpt) -> Result<(), Box<dyn std::error::Error>> {
+    if opt.output_path.exists() {
+        return Err(Box::new(crate::OptError::OutputFileExists(opt.output_path.clone())));
+    }
+
+    let unpacked: UnpackedAtlas = match File::open(&opt.metadata_path) {
+        Ok(file) => match serde_json::from_reader(file) {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(Box::new(PackError::CouldNotReadMetadataFile(opt.metadata_path.clone())));
+            }
+        },
+        Err(_) => {
+            return Err(Box::new(PackError::CouldNotReadMetadataFile(opt.metadata_path.clone())));
+        }
+    };
+
+    let image_path = match opt.image_path {
+        Some(ref path) => path.clone(),
+        None => {
+            let metadata_dir = opt.metadata_path.parent().unwrap_or(Path::new(""));
+            metadata_dir.join(&unpacked.image.file)
+        }
+    };
+    let image = match image::open(&image_path) {
+        Ok(val) => val.to_rgba(),
+        Err(_) => return Err(Box::new(PackError::CouldNotReadImageFile(image_path))),
+    };
+
+    let metadata = bmfa::BitmapFontAtlasMetadata::from(unpacked.metadata);
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    if width != metadata.width || height != metadata.height {
+        return Err(Box::new(
+            PackError::ImageDimensionsMismatch(width, height, metadata.width, metadata.height)
+        ));
+    }
+    if unpacked.image.origin != metadata.origin {
+        return Err(Box::new(PackError::OriginMismatch(unpacked.image.origin, metadata.origin)));
+    }
+
+    let mut image_data = image.into_raw();
+    if metadata.origin == bmfa::Origin::BottomLeft {
+        crate::flip_vertically(&mut image_data, 4 * width, height);
+    }
+    let atlas_image = bmfa::BitmapFontAtlasImage::new(image_data, width, height, metadata.origin);
+    let atlas = BitmapFontAtlas::new(metadata, atlas_image);
+
+    crate::atlas_file::write_atlas_file(&opt.output_path, &atlas)?;
+
+    Ok(())
+}
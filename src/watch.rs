@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use structopt::StructOpt;
+use crate::{AppError, Opt, OptError};
+
+
+/// The shell input options for `fontgen watch`.
+#[derive(Debug, StructOpt)]
+pub struct WatchOpt {
+    /// How often to check the watched files for changes, in milliseconds.
+    #[structopt(long = "interval", default_value = "500")]
+    pub interval: u64,
+    /// Render a preview of some sample text to this PNG image after each build.
+    #[structopt(parse(from_os_str))]
+    #[structopt(long = "preview-out")]
+    pub preview_path: Option<PathBuf>,
+    /// The sample text for the preview image.
+    #[structopt(long = "preview-text", default_value = "The quick brown fox jumps over the lazy dog")]
+    pub preview_text: String,
+    /// Draw the baselines and glyph boxes over the preview image.
+    #[structopt(long = "preview-overlay")]
+    pub preview_overlay: bool,
+}
+
+/// A snapshot of the last modification of a watched file. A file that cannot be read
+/// has no stamp, so a file that disappears and comes back counts as a change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+/// The files that trigger a rebuild when they change. These are all of the input files the
+/// options refer to: the font file, and the glyph name map when there is one.
+fn watched_files(opt: &Opt) -> Vec<PathBuf> {
+    let mut files = vec![opt.input_path().to_path_buf()];
+    if let Some(ref glyph_name_map) = opt.glyph_name_map {
+        files.push(glyph_name_map.clone());
+    }
+
+    files
+}
+
+/// Take a snapshot of each of the watched files, in the same order as the files.
+fn file_stamps(files: &[PathBuf]) -> Vec<Option<FileStamp>> {
+    files.iter().map(|file| {
+        fs::metadata(file).ok().map(|metadata| FileStamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }).collect()
+}
+
+/// Render the preview image from the atlas generated for the first glyph size.
+fn render_preview(opt: &Opt, watch_opt: &WatchOpt) -> Result<(), Box<dyn std::error::Error>> {
+    let preview_path = match watch_opt.preview_path {
+        Some(ref path) => path,
+        None => return Ok(()),
+    };
+
+    let atlas_path = crate::atlas_path(opt, opt.slot_glyph_sizes()[0]);
+    let atlas = match bmfa::load(&atlas_path) {
+        Ok(val) => val,
+        Err(_) => return Err(Box::new(AppError::CouldNotOpenAtlasFile(atlas_path))),
+    };
+    let metrics = crate::preview::load_atlas_metrics(&atlas_path)?;
+    let canvas = crate::preview::render_text(
+        &atlas, metrics.as_ref(), &watch_opt.preview_text, watch_opt.preview_overlay
+    );
+    if canvas.save(preview_path).is_err() {
+        return Err(Box::new(AppError::CouldNotCreateAtlasFile(preview_path.clone())));
+    }
+
+    Ok(())
+}
+
+/// Build the atlas and the preview image, reporting the outcome instead of returning it,
+/// so a bad edit never stops the watcher.
+fn rebuild(opt: &Opt, watch_opt: &WatchOpt) {
+    let result = crate::verify_opt(opt)
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)
+        .and_then(|_| crate::run_app(opt))
+        .and_then(|_| render_preview(opt, watch_opt));
+
+    match result {
+        Ok(_) => println!("Generated atlas from {}.", opt.input_path().display()),
+        Err(e) => eprintln!("Failed to generate atlas: {}", e),
+    }
+}
+
+/// Run the `watch` subcommand. This builds the atlas once, then polls the watched files
+/// and rebuilds whenever one of them changes. It only stops when the process is killed.
+pub fn run_watch(opt: &Opt, watch_opt: &WatchOpt) -> Result<(), Box<dyn std::error::Error>> {
+    if watch_opt.preview_path.is_some() && (opt.shared_atlas || !opt.writes_atlas_file()) {
+        return Err(Box::new(OptError::PreviewRequiresAtlasFile));
+    }
+
+    let files = watched_files(opt);
+    let interval = Duration::from_millis(watch_opt.interval);
+    let mut stamps = file_stamps(&files);
+    rebuild(opt, watch_opt);
+    for file in files.iter() {
+        println!("Watching {} for changes.", file.display());
+    }
+
+    loop {
+        thread::sleep(interval);
+        let new_stamps = file_stamps(&files);
+        if new_stamps != stamps {
+            stamps = new_stamps;
+            rebuild(opt, watch_opt);
+        }
+    }
+}
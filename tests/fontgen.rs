@@ -1,4 +1,5 @@
 use assert_cmd::prelude::*;
+use predicates::prelude::*;
 use std::fs;
 use std::process::Command;
 use std::path::Path;
@@ -61,3 +62,1415 @@ fn fontgen_should_reject_padding_larger_than_slot_glyph_size() -> Result<(), Box
 
     Ok(())
 }
+
+/// Generating several glyph sizes in one run should produce one atlas per size.
+#[test]
+fn generate_a_font_sheet_for_each_size() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoSizes.png")
+        .arg("--padding")
+        .arg("2")
+        .arg("--sizes")
+        .arg("16,24,32");
+    cmd.assert().success();
+
+    for size in &[16, 24, 32] {
+        let file_name = format!("FontMonoSizes-{}.bmfa", size);
+        let path = Path::new(&file_name);
+
+        assert!(path.exists());
+
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// A shared atlas should stack the atlas for each size into one texture, with the glyph
+/// positions rescaled to the shared texture and the glyph sizes left relative to the slot
+/// glyph size of each entry.
+#[test]
+fn generate_a_shared_font_sheet_for_each_size() -> Result<(), Box<std::error::Error>> {
+    for shared in &[false, true] {
+        let mut cmd = Command::cargo_bin("fontgen")?;
+        cmd.arg("--input")
+            .arg("assets/FreeMono.ttf")
+            .arg("--output")
+            .arg(if *shared { "FontMonoShared.png" } else { "FontMonoUnshared.png" })
+            .arg("--padding")
+            .arg("4")
+            .arg("--sizes")
+            .arg("16,32");
+        if *shared {
+            cmd.arg("--shared-atlas");
+        }
+        cmd.assert().success();
+    }
+
+    let shared: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontMonoShared.json")?)?;
+    let shared_width = shared["width"].as_f64().unwrap();
+    let shared_height = shared["height"].as_f64().unwrap();
+    let entries = shared["sizes"].as_array().unwrap();
+
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["y"], 0);
+    assert_eq!(entries[1]["y"], entries[0]["height"]);
+    assert_eq!(shared["height"].as_u64(), Some(entries.iter().map(|entry| entry["height"].as_u64().unwrap()).sum()));
+
+    for (entry, size) in entries.iter().zip(&[16, 32]) {
+        assert_eq!(entry["slot_glyph_size"], *size);
+
+        let atlas_path = format!("FontMonoUnshared-{}.bmfa", size);
+        let output = Command::cargo_bin("fontgen")?.arg("inspect").arg(&atlas_path).arg("--json").output()?;
+        let atlas: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        let atlas_width = atlas["width"].as_f64().unwrap();
+        let atlas_height = atlas["height"].as_f64().unwrap();
+        let entry_y = entry["y"].as_f64().unwrap();
+        for code_point in &["33", "65", "103", "126"] {
+            let glyph = &atlas["glyph_metadata"][code_point];
+            let shared_glyph = &entry["glyph_metadata"][code_point];
+            for field in &["width", "height", "y_offset"] {
+                assert_eq!(shared_glyph[field], glyph[field]);
+            }
+
+            let x_min = shared_glyph["x_min"].as_f64().unwrap() * shared_width;
+            let y_min = shared_glyph["y_min"].as_f64().unwrap() * shared_height;
+
+            assert!((x_min - glyph["x_min"].as_f64().unwrap() * atlas_width).abs() < 0.01);
+            assert!((y_min - entry_y - glyph["y_min"].as_f64().unwrap() * atlas_height).abs() < 0.01);
+        }
+
+        fs::remove_file(atlas_path)?;
+    }
+
+    fs::remove_file("FontMonoShared.png")?;
+    fs::remove_file("FontMonoShared.json")?;
+
+    Ok(())
+}
+
+/// Inspecting an atlas should print the metadata for the requested glyphs.
+#[test]
+fn inspect_a_font_sheet() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoInspect.png")
+        .arg("--padding")
+        .arg("6")
+        .arg("--slot-glyph-size")
+        .arg("64");
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("inspect")
+        .arg("FontMonoInspect.bmfa")
+        .arg("--json")
+        .arg("--code-point")
+        .arg("U+0041");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\"slot_glyph_size\": 64"))
+        .stdout(predicate::str::contains("\"code_point\": 65"));
+
+    fs::remove_file("FontMonoInspect.bmfa")?;
+
+    Ok(())
+}
+
+/// Unpacking an atlas and packing it back up should produce a new atlas file.
+#[test]
+fn unpack_and_pack_a_font_sheet() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoUnpack.png")
+        .arg("--padding")
+        .arg("6")
+        .arg("--slot-glyph-size")
+        .arg("64");
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("unpack")
+        .arg("FontMonoUnpack.bmfa")
+        .arg("--out-dir")
+        .arg("FontMonoUnpack");
+    cmd.assert().success();
+
+    assert!(Path::new("FontMonoUnpack/FontMonoUnpack.png").exists());
+    assert!(Path::new("FontMonoUnpack/FontMonoUnpack.json").exists());
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("pack")
+        .arg("FontMonoUnpack/FontMonoUnpack.json")
+        .arg("--output")
+        .arg("FontMonoRepack.bmfa");
+    cmd.assert().success();
+
+    assert!(Path::new("FontMonoRepack.bmfa").exists());
+
+    // The repacked atlas holds the same metadata and image as the original.
+    let inspect = |path: &str| -> Result<Vec<u8>, Box<std::error::Error>> {
+        Ok(Command::cargo_bin("fontgen")?.arg("inspect").arg(path).arg("--json").output()?.stdout)
+    };
+    assert_eq!(inspect("FontMonoRepack.bmfa")?, inspect("FontMonoUnpack.bmfa")?);
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("unpack")
+        .arg("FontMonoRepack.bmfa")
+        .arg("--out-dir")
+        .arg("FontMonoRepackUnpack");
+    cmd.assert().success();
+
+    let original = image::open("FontMonoUnpack/FontMonoUnpack.png")?.to_rgba();
+    let repacked = image::open("FontMonoRepackUnpack/FontMonoRepack.png")?.to_rgba();
+
+    assert_eq!(repacked.dimensions(), original.dimensions());
+    assert_eq!(repacked.into_raw(), original.into_raw());
+
+    // An edited image that no longer matches the dimensions in the metadata is rejected.
+    let cropped = image::RgbaImage::new(64, 64);
+    cropped.save("FontMonoUnpack/FontMonoCropped.png")?;
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("pack")
+        .arg("FontMonoUnpack/FontMonoUnpack.json")
+        .arg("--output")
+        .arg("FontMonoCropped.bmfa")
+        .arg("--image")
+        .arg("FontMonoUnpack/FontMonoCropped.png");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("ImageDimensionsMismatch(64, 64"));
+
+    assert!(!Path::new("FontMonoCropped.bmfa").exists());
+
+    fs::remove_file("FontMonoUnpack.bmfa")?;
+    fs::remove_file("FontMonoRepack.bmfa")?;
+    fs::remove_dir_all("FontMonoUnpack")?;
+    fs::remove_dir_all("FontMonoRepackUnpack")?;
+
+    Ok(())
+}
+
+/// Rendering a preview of some sample text should produce a PNG image.
+#[test]
+fn preview_sample_text_with_a_font_sheet() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoPreview.png")
+        .arg("--padding")
+        .arg("6")
+        .arg("--slot-glyph-size")
+        .arg("64");
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("preview")
+        .arg("--atlas")
+        .arg("FontMonoPreview.bmfa")
+        .arg("--text")
+        .arg("The quick brown fox")
+        .arg("--out")
+        .arg("FontMonoPreviewText.png")
+        .arg("--overlay");
+    cmd.assert().success();
+
+    let path = Path::new("FontMonoPreviewText.png");
+
+    assert!(path.exists());
+
+    // Without glyph metrics, the pen moves on by the width of each glyph rectangle, and the
+    // text sits inside a margin of a quarter of the slot glyph size.
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("preview")
+        .arg("--atlas")
+        .arg("FontMonoPreview.bmfa")
+        .arg("--text")
+        .arg("AH")
+        .arg("--out")
+        .arg("FontMonoPreviewGlyphs.png");
+    cmd.assert().success();
+
+    let output = Command::cargo_bin("fontgen")?.arg("inspect").arg("FontMonoPreview.bmfa").arg("--json").output()?;
+    let metadata: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    let rect = |code_point: &str| {
+        let glyph = &metadata["glyph_metadata"][code_point];
+        let size = |field: &str| (glyph[field].as_f64().unwrap() * 64.0).round() as i64;
+        (size("width") as u32, size("height") as u32, size("y_offset"))
+    };
+    let (a_width, a_height, a_offset) = rect("65");
+    let (h_width, h_height, h_offset) = rect("72");
+    let margin = 16;
+    let bottom = i64::max(0, -i64::min(a_offset, h_offset));
+    let top = i64::min(-64, i64::min(-a_offset - a_height as i64, -h_offset - h_height as i64));
+    let preview = image::open("FontMonoPreviewGlyphs.png")?.to_rgba();
+
+    assert_eq!(preview.dimensions(), (a_width + h_width + 2 * margin, (bottom - top) as u32 + 2 * margin));
+
+    let lit = |left: u32, right: u32| (left..right).any(|x| {
+        (0..preview.height()).any(|y| preview.get_pixel(x, y).data[0] > 0)
+    });
+
+    assert!(lit(margin, margin + a_width));
+    assert!(lit(margin + a_width, margin + a_width + h_width));
+    assert!(!lit(0, margin));
+
+    fs::remove_file("FontMonoPreview.bmfa")?;
+    fs::remove_file("FontMonoPreviewGlyphs.png")?;
+    fs::remove_file(path)?;
+
+    Ok(())
+}
+
+/// The glyph slots fill the atlas row by row, so the metadata of a glyph in a later row
+/// should point at that row.
+#[test]
+fn glyph_metadata_points_at_the_row_of_each_slot() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoRows.png")
+        .arg("--padding")
+        .arg("6")
+        .arg("--slot-glyph-size")
+        .arg("64");
+    cmd.assert().success();
+
+    let output = Command::cargo_bin("fontgen")?
+        .arg("inspect")
+        .arg("FontMonoRows.bmfa")
+        .arg("--json")
+        .arg("--code-point")
+        .arg("U+0071")
+        .output()?;
+    let metadata: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    let glyph = &metadata["glyph_metadata"]["113"];
+
+    // The glyph for `q` sits in the slot at row 5 and column 1 of a 1024x1024 atlas.
+    assert_eq!(metadata["width"], 1024);
+    assert_eq!(metadata["height"], 1024);
+    assert_eq!(glyph["row"], 5);
+    assert_eq!(glyph["column"], 1);
+    assert_eq!(glyph["x_min"], 0.0625);
+    assert_eq!(glyph["y_min"], 0.3125);
+
+    fs::remove_file("FontMonoRows.bmfa")?;
+
+    Ok(())
+}
+
+/// Generating a font sheet with a debug overlay should write the overlay image next to the atlas,
+/// the same size as the atlas, with the boundary of every slot and of every glyph drawn on it.
+#[test]
+fn generate_a_font_sheet_with_a_debug_overlay() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoOverlay.png")
+        .arg("--padding")
+        .arg("6")
+        .arg("--slot-glyph-size")
+        .arg("64")
+        .arg("--debug-overlay")
+        .arg("FontMonoOverlayDebug.png");
+    cmd.assert().success();
+
+    let atlas_path = Path::new("FontMonoOverlay.bmfa");
+    let overlay_path = Path::new("FontMonoOverlayDebug.png");
+
+    assert!(atlas_path.exists());
+    assert!(overlay_path.exists());
+
+    let overlay = image::open(overlay_path)?.to_rgba();
+    assert_eq!(overlay.dimensions(), (1024, 1024));
+    // The last row of slots is empty, so nothing is drawn over the edges of its slots.
+    let slot_color = [128, 128, 128, 255];
+    assert_eq!(overlay.get_pixel(992, 960).data, slot_color);
+    assert_eq!(overlay.get_pixel(960, 992).data, slot_color);
+    // The A sits in the slot at column 1 and row 2, and its glyph rectangle is 41 by 39
+    // pixels from the top left corner of the slot.
+    let glyph_rect_color = [0, 255, 0, 255];
+    assert_eq!(overlay.get_pixel(84, 128).data, glyph_rect_color);
+    assert_eq!(overlay.get_pixel(84, 166).data, glyph_rect_color);
+    assert_eq!(overlay.get_pixel(64, 150).data, glyph_rect_color);
+    assert_eq!(overlay.get_pixel(104, 150).data, glyph_rect_color);
+
+    fs::remove_file(atlas_path)?;
+    fs::remove_file(overlay_path)?;
+
+    Ok(())
+}
+
+/// Generating a font sheet with several worker threads should produce the same atlas as
+/// generating it on a single thread.
+#[test]
+fn generate_a_font_sheet_with_several_jobs() -> Result<(), Box<std::error::Error>> {
+    for jobs in &["1", "4"] {
+        let mut cmd = Command::cargo_bin("fontgen")?;
+        cmd.arg("--input")
+            .arg("assets/FreeMono.ttf")
+            .arg("--output")
+            .arg(format!("FontMonoJobs{}.png", jobs))
+            .arg("--padding")
+            .arg("6")
+            .arg("--slot-glyph-size")
+            .arg("64")
+            .arg("--jobs")
+            .arg(jobs);
+        cmd.assert().success();
+
+        let mut cmd = Command::cargo_bin("fontgen")?;
+        cmd.arg("unpack")
+            .arg(format!("FontMonoJobs{}.bmfa", jobs))
+            .arg("--out-dir")
+            .arg(format!("FontMonoJobs{}", jobs));
+        cmd.assert().success();
+    }
+
+    let single = image::open("FontMonoJobs1/FontMonoJobs1.png")?.to_rgba();
+    let several = image::open("FontMonoJobs4/FontMonoJobs4.png")?.to_rgba();
+
+    assert_eq!(several.dimensions(), single.dimensions());
+    assert_eq!(several.into_raw(), single.into_raw());
+    assert_eq!(fs::read("FontMonoJobs4.bmfa")?, fs::read("FontMonoJobs1.bmfa")?);
+
+    for jobs in &["1", "4"] {
+        fs::remove_file(format!("FontMonoJobs{}.bmfa", jobs))?;
+        fs::remove_dir_all(format!("FontMonoJobs{}", jobs))?;
+    }
+
+    Ok(())
+}
+
+/// Generating the same atlas twice should produce byte for byte identical output, both for a
+/// shared atlas and for a plain atlas file, no matter how many jobs are used.
+#[test]
+fn generating_the_same_font_sheet_twice_is_deterministic() -> Result<(), Box<std::error::Error>> {
+    for (directory, jobs) in &[("Deterministic1", "1"), ("Deterministic2", "4")] {
+        fs::create_dir_all(directory)?;
+        let mut cmd = Command::cargo_bin("fontgen")?;
+        cmd.arg("--input")
+            .arg("assets/FreeMono.ttf")
+            .arg("--output")
+            .arg(Path::new(directory).join("FontMono.png"))
+            .arg("--padding")
+            .arg("4")
+            .arg("--sizes")
+            .arg("16,32")
+            .arg("--shared-atlas")
+            .arg("--jobs")
+            .arg(jobs);
+        cmd.assert().success();
+
+        let mut cmd = Command::cargo_bin("fontgen")?;
+        cmd.arg("--input")
+            .arg("assets/FreeMono.ttf")
+            .arg("--output")
+            .arg(Path::new(directory).join("FontMonoPlain.png"))
+            .arg("--padding")
+            .arg("4")
+            .arg("--slot-glyph-size")
+            .arg("32")
+            .arg("--jobs")
+            .arg(jobs);
+        cmd.assert().success();
+    }
+
+    for file_name in &["FontMono.png", "FontMono.json", "FontMonoPlain.bmfa"] {
+        let first = fs::read(Path::new("Deterministic1").join(file_name))?;
+        let second = fs::read(Path::new("Deterministic2").join(file_name))?;
+
+        assert_eq!(first, second);
+    }
+
+    fs::remove_dir_all("Deterministic1")?;
+    fs::remove_dir_all("Deterministic2")?;
+
+    Ok(())
+}
+
+/// A second run with the same font and options should restore the atlas from the cache,
+/// with the same bytes the first run wrote.
+#[test]
+fn generate_a_font_sheet_from_the_cache() -> Result<(), Box<std::error::Error>> {
+    let mut first_run = vec![];
+    for run in 0..2 {
+        let mut cmd = Command::cargo_bin("fontgen")?;
+        cmd.arg("--input")
+            .arg("assets/FreeMono.ttf")
+            .arg("--output")
+            .arg("FontMonoCached.png")
+            .arg("--padding")
+            .arg("6")
+            .arg("--slot-glyph-size")
+            .arg("64")
+            .arg("--cache-dir")
+            .arg("FontMonoCache");
+        let restored = predicate::str::contains("Restored the output from the cache");
+        if run == 0 {
+            cmd.assert().success().stdout(restored.not());
+        } else {
+            cmd.assert().success().stdout(restored);
+        }
+
+        let path = Path::new("FontMonoCached.bmfa");
+
+        assert!(path.exists());
+
+        if run == 0 {
+            first_run = fs::read(path)?;
+        } else {
+            assert_eq!(fs::read(path)?, first_run);
+        }
+
+        fs::remove_file(path)?;
+    }
+
+    // The first run also cached each rendered glyph.
+    let glyph_dirs: Vec<_> = fs::read_dir("FontMonoCache")?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("glyphs-"))
+        .collect();
+
+    assert_eq!(glyph_dirs.len(), 1);
+    assert!(fs::read_dir(glyph_dirs[0].path())?.count() > 0);
+
+    fs::remove_dir_all("FontMonoCache")?;
+
+    Ok(())
+}
+
+/// Trimming the glyphs should pack them into a shorter atlas, and write the glyph metrics
+/// next to the atlas file. The metrics of each glyph should place it relative to the pen
+/// position the same way as in an atlas whose glyphs are not trimmed.
+#[test]
+fn generate_a_font_sheet_with_trimmed_glyphs() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoTrimmed.png")
+        .arg("--padding")
+        .arg("6")
+        .arg("--slot-glyph-size")
+        .arg("64")
+        .arg("--trim");
+    cmd.assert().success();
+
+    let metrics_path = Path::new("FontMonoTrimmed.metrics.json");
+
+    assert!(metrics_path.exists());
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("inspect")
+        .arg("FontMonoTrimmed.bmfa")
+        .arg("--json");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\"width\": 1024"))
+        .stdout(predicate::str::contains("\"height\": 1024").not());
+
+    // A gutter makes the untrimmed atlas write its glyph metrics too.
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoUntrimmed.png")
+        .arg("--padding")
+        .arg("6")
+        .arg("--slot-glyph-size")
+        .arg("64")
+        .arg("--spacing")
+        .arg("1");
+    cmd.assert().success();
+
+    let trimmed: serde_json::Value = serde_json::from_str(&fs::read_to_string(metrics_path)?)?;
+    let untrimmed: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontMonoUntrimmed.metrics.json")?)?;
+    // The period and the underscore sit on and below the baseline.
+    for &(code_point, width, height, x_offset, y_offset) in [("46", 15, 14, 10, 10), ("95", 41, 10, -3, -1)].iter() {
+        let glyph = &trimmed["glyphs"][code_point];
+        assert_eq!(glyph["width"], width);
+        assert_eq!(glyph["height"], height);
+        assert_eq!(glyph["x_offset"], x_offset);
+        assert_eq!(glyph["y_offset"], y_offset);
+        for field in ["width", "height", "x_offset", "y_offset", "advance"].iter() {
+            assert_eq!(glyph[field], untrimmed["glyphs"][code_point][field]);
+        }
+    }
+
+    fs::remove_file("FontMonoTrimmed.bmfa")?;
+    fs::remove_file(metrics_path)?;
+    fs::remove_file("FontMonoUntrimmed.bmfa")?;
+    fs::remove_file("FontMonoUntrimmed.metrics.json")?;
+
+    Ok(())
+}
+
+/// Deduplicating trimmed glyphs should report how much space sharing glyph images saves.
+#[test]
+fn generate_a_font_sheet_with_deduplicated_glyphs() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoDeduplicated.png")
+        .arg("--padding")
+        .arg("6")
+        .arg("--slot-glyph-size")
+        .arg("64")
+        .arg("--trim")
+        .arg("--dedupe");
+    let output = cmd.output()?;
+
+    assert!(output.status.success());
+
+    // The message reads "Deduplicated <count> glyph images ...".
+    let stdout = String::from_utf8(output.stdout)?;
+    let shared_glyphs = stdout.split("Deduplicated ").nth(1)
+        .and_then(|message| message.split_whitespace().next())
+        .map(|count| count.parse::<usize>())
+        .unwrap()?;
+
+    assert!(shared_glyphs > 0);
+
+    // FreeMono has no glyphs for the C1 control codes, so they all map to glyph index zero
+    // and share one rectangle.
+    let metrics: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontMonoDeduplicated.metrics.json")?)?;
+    let first = &metrics["glyphs"]["128"];
+    let second = &metrics["glyphs"]["129"];
+
+    assert_eq!(first["glyph_id"], second["glyph_id"]);
+    for field in &["x", "y", "width", "height"] {
+        assert_eq!(first[field], second[field]);
+    }
+
+    fs::remove_file("FontMonoDeduplicated.bmfa")?;
+    fs::remove_file("FontMonoDeduplicated.metrics.json")?;
+
+    Ok(())
+}
+
+/// Extruding the glyphs and spacing them apart should widen the atlas by the gutter between
+/// the slots, and describe the gutter in the metrics file.
+#[test]
+fn generate_a_font_sheet_with_a_gutter() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoGutter.png")
+        .arg("--slot-glyph-size")
+        .arg("32")
+        .arg("--extrude")
+        .arg("2")
+        .arg("--spacing")
+        .arg("3");
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("inspect")
+        .arg("FontMonoGutter.bmfa")
+        .arg("--json");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\"width\": 621"));
+
+    let metrics = fs::read_to_string("FontMonoGutter.metrics.json")?;
+
+    assert!(metrics.contains("\"extrude\": 2"));
+    assert!(metrics.contains("\"spacing\": 3"));
+
+    fs::remove_file("FontMonoGutter.bmfa")?;
+    fs::remove_file("FontMonoGutter.metrics.json")?;
+
+    Ok(())
+}
+
+/// Generating mip levels should store them in the texture file next to the atlas, and needs
+/// one to store them in. Glyphs packed too closely to keep apart at the smallest level should
+/// be rejected before anything gets written.
+#[test]
+fn generate_a_font_sheet_with_mipmaps() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoMipmaps.png")
+        .arg("--slot-glyph-size")
+        .arg("32")
+        .arg("--mipmaps")
+        .arg("2");
+    cmd.assert().failure().stderr(predicate::str::contains("MipmapsRequireImageFormat"));
+    assert!(!Path::new("FontMonoMipmaps.bmfa").exists());
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoMipmaps.png")
+        .arg("--slot-glyph-size")
+        .arg("32")
+        .arg("--mipmaps")
+        .arg("2")
+        .arg("--image-format")
+        .arg("dds");
+    cmd.assert().success();
+
+    // Three levels: the atlas image and two mip levels, and no images of them on the side.
+    let texture = fs::read("FontMonoMipmaps.dds")?;
+    assert_eq!(&texture[28..32], &[3, 0, 0, 0]);
+    assert!(!Path::new("FontMonoMipmaps.mip1.png").exists());
+    fs::remove_file("FontMonoMipmaps.dds")?;
+    fs::remove_file("FontMonoMipmaps.metadata.json")?;
+    fs::remove_file("FontMonoMipmaps.bmfa")?;
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoMipmapsTrimmed.png")
+        .arg("--slot-glyph-size")
+        .arg("32")
+        .arg("--trim")
+        .arg("--mipmaps")
+        .arg("2")
+        .arg("--image-format")
+        .arg("ktx2")
+        .arg("--debug-overlay")
+        .arg("FontMonoMipmapsTrimmed.overlay.png");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("MipLevelsOverlap(1,"))
+        .stderr(predicate::str::contains("Increase the spacing between glyphs with --spacing"));
+    assert!(!Path::new("FontMonoMipmapsTrimmed.overlay.png").exists());
+    assert!(!Path::new("FontMonoMipmapsTrimmed.ktx2").exists());
+    assert!(!Path::new("FontMonoMipmapsTrimmed.metrics.json").exists());
+
+    // Enough spacing keeps the glyphs apart at both levels.
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoMipmapsTrimmed.png")
+        .arg("--slot-glyph-size")
+        .arg("32")
+        .arg("--trim")
+        .arg("--spacing")
+        .arg("4")
+        .arg("--mipmaps")
+        .arg("2")
+        .arg("--image-format")
+        .arg("ktx2");
+    cmd.assert().success();
+
+    let texture = fs::read("FontMonoMipmapsTrimmed.ktx2")?;
+    assert_eq!(&texture[40..44], &[3, 0, 0, 0]);
+    fs::remove_file("FontMonoMipmapsTrimmed.ktx2")?;
+    fs::remove_file("FontMonoMipmapsTrimmed.metrics.json")?;
+    fs::remove_file("FontMonoMipmapsTrimmed.bmfa")?;
+
+    Ok(())
+}
+
+/// Generating a font sheet with `--image-format` should write a KTX2 texture holding the atlas
+/// and its mip levels, or a DDS texture with the glyph metadata in a JSON file next to it.
+#[test]
+fn generate_a_font_sheet_with_a_texture_file() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoTexture.png")
+        .arg("--slot-glyph-size")
+        .arg("32")
+        .arg("--mipmaps")
+        .arg("1")
+        .arg("--image-format")
+        .arg("ktx2");
+    cmd.assert().success();
+
+    let texture = fs::read("FontMonoTexture.ktx2")?;
+    assert_eq!(&texture[0..12], &[0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A]);
+    // Two levels: the atlas image and one mip level.
+    assert_eq!(&texture[40..44], &[2, 0, 0, 0]);
+    fs::remove_file("FontMonoTexture.ktx2")?;
+    fs::remove_file("FontMonoTexture.bmfa")?;
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoTexture.png")
+        .arg("--slot-glyph-size")
+        .arg("32")
+        .arg("--image-format")
+        .arg("dds");
+    cmd.assert().success();
+
+    let texture = fs::read("FontMonoTexture.dds")?;
+    assert_eq!(&texture[0..4], b"DDS ");
+    assert!(Path::new("FontMonoTexture.metadata.json").exists());
+    fs::remove_file("FontMonoTexture.dds")?;
+    fs::remove_file("FontMonoTexture.metadata.json")?;
+    fs::remove_file("FontMonoTexture.bmfa")?;
+
+    Ok(())
+}
+
+/// The JSON metadata written with `--format json` should validate against the JSON Schema
+/// shipped in the source tree.
+#[test]
+fn generate_json_metadata_matching_the_schema() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoJson.png")
+        .arg("--slot-glyph-size")
+        .arg("32")
+        .arg("--padding")
+        .arg("2")
+        .arg("--trim")
+        .arg("--format")
+        .arg("json");
+    cmd.assert().success();
+
+    let schema: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string("schemas/fontgen-atlas-v1.schema.json")?
+    )?;
+    let schema = jsonschema::JSONSchema::compile(&schema).expect("The schema should be valid.");
+    let metadata: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontMonoJson.atlas.json")?)?;
+    if let Err(errors) = schema.validate(&metadata) {
+        for error in errors {
+            eprintln!("{}: {}", error.instance_path, error);
+        }
+        panic!("The JSON metadata does not match the schema.");
+    }
+    assert_eq!(metadata["glyphs"]["65"]["code_point"], 65);
+
+    fs::remove_file("FontMonoJson.atlas.json")?;
+    fs::remove_file("FontMonoJson.metrics.json")?;
+    fs::remove_file("FontMonoJson.bmfa")?;
+
+    Ok(())
+}
+
+/// Export a font sheet in the JSON layout of msdf-atlas-gen.
+#[test]
+fn generate_msdf_atlas_json() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoMsdf.png")
+        .arg("--slot-glyph-size")
+        .arg("32")
+        .arg("--format")
+        .arg("msdf-atlas-json");
+    cmd.assert().success();
+
+    let metadata: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontMonoMsdf.msdf.json")?)?;
+    assert_eq!(metadata["atlas"]["yOrigin"], "bottom");
+    assert_eq!(metadata["metrics"]["emSize"], 1);
+    let glyphs = metadata["glyphs"].as_array().expect("The glyphs should be a list.");
+    let glyph = glyphs.iter().find(|glyph| glyph["unicode"] == 65).expect("The glyph for A should exist.");
+    assert!(glyph["planeBounds"]["top"].is_number());
+    assert!(glyph["atlasBounds"]["right"].is_number());
+    assert!(metadata["kerning"].is_array());
+
+    fs::remove_file("FontMonoMsdf.msdf.json")?;
+    fs::remove_file("FontMonoMsdf.bmfa")?;
+
+    Ok(())
+}
+
+/// Compile a generated Rust module as a `no_std` library, along with some code using it.
+/// The library is written into the directory `out_dir`.
+fn compile_rust_module(module_path: &str, usage: &str, out_dir: &str) -> Result<(), Box<std::error::Error>> {
+    let module_path = fs::canonicalize(module_path)?;
+    let wrapper_path = Path::new(out_dir).join("lib.rs");
+    fs::create_dir_all(out_dir)?;
+    fs::write(&wrapper_path, format!(
+        "#![no_std]\n#![deny(warnings)]\n\n#[path = {:?}]\npub mod font;\n\n{}\n", module_path, usage
+    ))?;
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let output = Command::new(rustc)
+        .arg("--edition")
+        .arg("2018")
+        .arg("--crate-type")
+        .arg("lib")
+        .arg("--crate-name")
+        .arg("font")
+        .arg("--out-dir")
+        .arg(out_dir)
+        .arg(&wrapper_path)
+        .output()?;
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    Ok(())
+}
+
+/// Export a font sheet as a Rust module, which should compile without the standard library.
+#[test]
+fn generate_a_rust_module() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoRust.png")
+        .arg("--slot-glyph-size")
+        .arg("16")
+        .arg("--format")
+        .arg("rust");
+    cmd.assert().success();
+
+    let source = fs::read_to_string("FontMonoRust.rs")?;
+    assert!(source.contains("pub static PIXELS: [u8; 65536] = ["));
+    assert!(source.contains("Glyph { code_point: '\\u{41}'"));
+    assert!(source.contains("pub fn glyph(code_point: char) -> Option<&'static Glyph> {"));
+    assert!(!source.contains("bmfa"));
+
+    compile_rust_module("FontMonoRust.rs", "\
+        pub fn coverage(code_point: char) -> Option<u8> {
+            let glyph = font::glyph(code_point)?;
+            let index = glyph.y as usize * font::ATLAS_WIDTH + glyph.x as usize;
+            Some(font::PIXELS[index])
+        }", "FontMonoRustCompiled")?;
+
+    fs::remove_dir_all("FontMonoRustCompiled")?;
+    fs::remove_file("FontMonoRust.rs")?;
+    fs::remove_file("FontMonoRust.bmfa")?;
+
+    Ok(())
+}
+
+/// Export a font sheet as a C header with a symbol prefix.
+#[test]
+fn generate_a_c_header() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoHeader.png")
+        .arg("--slot-glyph-size")
+        .arg("16")
+        .arg("--format")
+        .arg("c-header")
+        .arg("--symbol-prefix")
+        .arg("mono");
+    cmd.assert().success();
+
+    let source = fs::read_to_string("FontMonoHeader.h")?;
+    assert!(source.contains("#ifndef MONO_H"));
+    assert!(source.contains("static const uint8_t mono_pixels[65536] = {"));
+    assert!(source.contains("static const mono_glyph mono_glyphs[MONO_GLYPH_COUNT] = {"));
+    assert!(source.contains("mono_kerning["));
+    assert!(source.contains("static const mono_font_metrics mono_metrics = {"));
+
+    fs::remove_file("FontMonoHeader.h")?;
+    fs::remove_file("FontMonoHeader.bmfa")?;
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoHeader.png")
+        .arg("--format")
+        .arg("c-header")
+        .arg("--symbol-prefix")
+        .arg("1-mono");
+    cmd.assert().failure();
+
+    Ok(())
+}
+
+/// A monochrome atlas should write the packed bitmap of each glyph in place of the atlas file,
+/// in each bit order and page layout, and Rust modules generated from it should compile.
+#[test]
+fn generate_a_monochrome_font_sheet() -> Result<(), Box<std::error::Error>> {
+    // The `A` of Tiny8x8 is five pixels wide and seven pixels tall:
+    //
+    //     .###.
+    //     #...#
+    //     #...#
+    //     #####
+    //     #...#
+    //     #...#
+    //     #...#
+    let packings: [(&str, &str, &[u8]); 4] = [
+        ("msb-first", "rows", &[0x70, 0x88, 0x88, 0xF8, 0x88, 0x88, 0x88]),
+        ("lsb-first", "rows", &[0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+        ("msb-first", "columns", &[0x7E, 0x90, 0x90, 0x90, 0x7E]),
+        ("lsb-first", "columns", &[0x7E, 0x09, 0x09, 0x09, 0x7E]),
+    ];
+    for &(bit_order, page_layout, expected) in packings.iter() {
+        let mut cmd = Command::cargo_bin("fontgen")?;
+        cmd.arg("--input")
+            .arg("assets/Tiny8x8.bdf")
+            .arg("--output")
+            .arg("FontTinyPacked.png")
+            .arg("--slot-glyph-size")
+            .arg("8")
+            .arg("--trim")
+            .arg("--mono")
+            .arg("--bit-order")
+            .arg(bit_order)
+            .arg("--page-layout")
+            .arg(page_layout);
+        cmd.assert().success();
+
+        let table: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontTinyPacked.packed.json")?)?;
+        let bitmaps = fs::read("FontTinyPacked.packed.bin")?;
+        let glyph = &table["glyphs"]["65"];
+        let offset = glyph["offset"].as_u64().unwrap() as usize;
+
+        assert_eq!(table["bitmap_file"], "FontTinyPacked.packed.bin");
+        assert_eq!(table["bit_order"], bit_order);
+        assert_eq!(table["page_layout"], page_layout);
+        assert_eq!(glyph["width"], 5);
+        assert_eq!(glyph["height"], 7);
+        assert_eq!(&bitmaps[offset..(offset + expected.len())], expected);
+
+        // The packed bitmaps take the place of the atlas file.
+        assert!(!Path::new("FontTinyPacked.bmfa").exists());
+        fs::remove_file("FontTinyPacked.packed.json")?;
+        fs::remove_file("FontTinyPacked.packed.bin")?;
+        fs::remove_file("FontTinyPacked.metrics.json")?;
+    }
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoPacked.png")
+        .arg("--slot-glyph-size")
+        .arg("16")
+        .arg("--mono")
+        .arg("--bit-order")
+        .arg("lsb-first")
+        .arg("--page-layout")
+        .arg("columns")
+        .arg("--format")
+        .arg("rust");
+    cmd.assert().success();
+
+    let source = fs::read_to_string("FontMonoPacked.rs")?;
+    assert!(source.contains("pub static BITMAPS: [u8; "));
+    assert!(source.contains("one byte per column in each page with the top pixel in the least significant bit"));
+    assert!(!source.contains("pub static PIXELS"));
+
+    compile_rust_module("FontMonoPacked.rs", "\
+        pub fn first_page(code_point: char) -> Option<u8> {
+            let glyph = font::glyph(code_point)?;
+            Some(font::BITMAPS[glyph.bitmap as usize])
+        }", "FontMonoPackedCompiled")?;
+
+    fs::remove_dir_all("FontMonoPackedCompiled")?;
+    fs::remove_file("FontMonoPacked.rs")?;
+    fs::remove_file("FontMonoPacked.packed.json")?;
+    fs::remove_file("FontMonoPacked.packed.bin")?;
+
+    // The JSON export describes the atlas image, so it needs the atlas file.
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoPacked.png")
+        .arg("--slot-glyph-size")
+        .arg("16")
+        .arg("--mono")
+        .arg("--format")
+        .arg("json");
+    cmd.assert().failure().stderr(predicate::str::contains("ImageExportRequiresRgbaAtlas(\"json\")"));
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoPacked.png")
+        .arg("--slot-glyph-size")
+        .arg("16")
+        .arg("--mono")
+        .arg("--rgba-atlas")
+        .arg("--format")
+        .arg("json");
+    cmd.assert().success();
+
+    let metadata: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontMonoPacked.atlas.json")?)?;
+    assert_eq!(metadata["atlas"]["file"], "FontMonoPacked.bmfa");
+    assert!(Path::new("FontMonoPacked.bmfa").exists());
+    fs::remove_file("FontMonoPacked.atlas.json")?;
+    fs::remove_file("FontMonoPacked.packed.json")?;
+    fs::remove_file("FontMonoPacked.packed.bin")?;
+    fs::remove_file("FontMonoPacked.bmfa")?;
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoPacked.png")
+        .arg("--threshold")
+        .arg("128");
+    cmd.assert().failure();
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontMonoPacked.png")
+        .arg("--sizes")
+        .arg("8,16")
+        .arg("--shared-atlas")
+        .arg("--mono");
+    cmd.assert().failure();
+
+    Ok(())
+}
+
+/// Generate a font sheet from a BDF bitmap font. The glyphs get copied from its only strike
+/// instead of being rasterized, and the `strikes` subcommand lists the strike.
+#[test]
+fn generate_a_font_sheet_from_a_bdf_file() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("strikes")
+        .arg("assets/Tiny8x8.bdf");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(r"\s8\s+5\s+8\n")?);
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/Tiny8x8.bdf")
+        .arg("--output")
+        .arg("FontTiny.png")
+        .arg("--slot-glyph-size")
+        .arg("10")
+        .arg("--format")
+        .arg("json");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Using the 8 pixel bitmap strike"));
+
+    let export = fs::read_to_string("FontTiny.atlas.json")?;
+    assert!(export.contains("\"size\": 8"));
+    assert!(export.contains("\"ascender\": 7.0"));
+
+    fs::remove_file("FontTiny.atlas.json")?;
+    fs::remove_file("FontTiny.bmfa")?;
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontTiny.png")
+        .arg("--use-embedded-bitmaps");
+    cmd.assert().failure();
+    assert!(!Path::new("FontTiny.bmfa").exists());
+
+    Ok(())
+}
+
+/// Generate a font sheet from a COLR font. The layers of the color glyph should be composited
+/// in their palette colors, and the glyph should be marked as a color glyph in the metadata.
+#[test]
+fn generate_a_font_sheet_with_color_glyphs() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/ColorSquares.ttf")
+        .arg("--output")
+        .arg("FontColor.png")
+        .arg("--slot-glyph-size")
+        .arg("16")
+        .arg("--origin")
+        .arg("top-left")
+        .arg("--color")
+        .arg("--format")
+        .arg("json");
+    cmd.assert().success();
+
+    let metadata: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontColor.atlas.json")?)?;
+    assert_eq!(metadata["glyphs"]["35"]["color"], true);
+    assert!(metadata["glyphs"]["65"].get("color").is_none());
+    let metrics: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontColor.metrics.json")?)?;
+    assert_eq!(metrics["glyphs"]["35"]["color"], true);
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("unpack")
+        .arg("FontColor.bmfa")
+        .arg("--out-dir")
+        .arg("FontColor");
+    cmd.assert().success();
+
+    // The glyph is a red square with a blue square on top of it in the middle.
+    let image = image::open("FontColor/FontColor.png")?.to_rgba();
+    let glyph = &metrics["glyphs"]["35"];
+    let x = glyph["x"].as_u64().unwrap() as u32;
+    let y = glyph["y"].as_u64().unwrap() as u32;
+    let width = glyph["width"].as_u64().unwrap() as u32;
+    let height = glyph["height"].as_u64().unwrap() as u32;
+    assert_eq!(image.get_pixel(x + 1, y + 1).data, [255, 0, 0, 255]);
+    assert_eq!(image.get_pixel(x + width / 2, y + height / 2).data, [0, 0, 255, 255]);
+
+    fs::remove_file("FontColor.atlas.json")?;
+    fs::remove_file("FontColor.metrics.json")?;
+    fs::remove_file("FontColor.bmfa")?;
+    fs::remove_dir_all("FontColor")?;
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/ColorSquares.ttf")
+        .arg("--output")
+        .arg("FontColor.png")
+        .arg("--color")
+        .arg("--mono");
+    cmd.assert().failure();
+
+    Ok(())
+}
+
+/// The vertical metrics of a font made for vertical text should be exported, and fonts
+/// without them should get a vertical advance of one line height.
+#[test]
+fn generate_vertical_metrics() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/VerticalSquares.ttf")
+        .arg("--output")
+        .arg("FontVertical.png")
+        .arg("--slot-glyph-size")
+        .arg("20")
+        .arg("--format")
+        .arg("json");
+    cmd.assert().success();
+
+    // The glyph sits 2 pixels below the pen, in a vertical advance of 24 pixels.
+    let metadata: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontVertical.atlas.json")?)?;
+    assert_eq!(metadata["glyphs"]["65"]["vertical_advance"], 24.0);
+    assert_eq!(metadata["glyphs"]["65"]["vertical_plane_bounds"]["top"], -2.0);
+
+    fs::remove_file("FontVertical.atlas.json")?;
+    fs::remove_file("FontVertical.bmfa")?;
+
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/FreeMono.ttf")
+        .arg("--output")
+        .arg("FontVertical.png")
+        .arg("--slot-glyph-size")
+        .arg("32")
+        .arg("--format")
+        .arg("json");
+    cmd.assert().success();
+
+    let metadata: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontVertical.atlas.json")?)?;
+    // FreeType rounds the line height to whole pixels.
+    let line_height = metadata["font"]["line_height"].as_f64().unwrap();
+    let vertical_advance = metadata["glyphs"]["65"]["vertical_advance"].as_f64().unwrap();
+    assert!((vertical_advance - line_height).abs() <= 1.0);
+
+    fs::remove_file("FontVertical.atlas.json")?;
+    fs::remove_file("FontVertical.bmfa")?;
+
+    Ok(())
+}
+
+/// Applying GSUB features should add the substituted glyphs to the atlas, keyed by glyph
+/// index, and write the substitutions next to the atlas file.
+#[test]
+fn generate_a_font_sheet_with_ligatures() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/LigatureSquares.ttf")
+        .arg("--output")
+        .arg("FontLigatures.bmfa")
+        .arg("--trim")
+        .arg("--features")
+        .arg("liga,smcp,calt");
+    cmd.assert().success();
+
+    // The f_i ligature is glyph 4, the small capital a is glyph 6, and the arrow shaped
+    // hyphen is glyph 9.
+    let substitutions: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontLigatures.substitutions.json")?)?;
+    let substitutions = substitutions["substitutions"].as_array().unwrap();
+    assert_eq!(substitutions.len(), 3);
+    assert_eq!(substitutions[0]["kind"], "ligature");
+    assert_eq!(substitutions[0]["input"], serde_json::json!([102, 105]));
+    assert_eq!(substitutions[0]["output"], serde_json::json!([0xF0004]));
+    assert_eq!(substitutions[1]["input"], serde_json::json!([97]));
+    assert_eq!(substitutions[1]["output"], serde_json::json!([0xF0006]));
+    assert_eq!(substitutions[2]["output"], serde_json::json!([0xF0009]));
+    assert_eq!(substitutions[2]["contextual"], true);
+
+    let metrics: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontLigatures.metrics.json")?)?;
+    for key in ["983044", "983046", "983049"].iter() {
+        assert!(metrics["glyphs"][key]["width"].as_u64().unwrap() > 0);
+    }
+
+    fs::remove_file("FontLigatures.substitutions.json")?;
+    fs::remove_file("FontLigatures.metrics.json")?;
+    fs::remove_file("FontLigatures.bmfa")?;
+
+    Ok(())
+}
+
+/// Glyphs picked by glyph index should be keyed by glyph index, unless a code point already
+/// puts them into the atlas, and every glyph should record its glyph index. Their kerning
+/// should be exported under the same keys.
+#[test]
+fn generate_a_font_sheet_by_glyph_index() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/LigatureSquares.ttf")
+        .arg("--output")
+        .arg("FontGlyphIds.bmfa")
+        .arg("--trim")
+        .arg("--glyph-ids")
+        .arg("2-4,6")
+        .arg("--format")
+        .arg("json");
+    cmd.assert().success();
+
+    // Glyphs 2 and 3 are the f and the i, and glyphs 4 and 6 have no code point.
+    let metadata: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontGlyphIds.atlas.json")?)?;
+    let glyphs = &metadata["glyphs"];
+    assert_eq!(glyphs["102"]["glyph_id"], 2);
+    assert_eq!(glyphs["105"]["glyph_id"], 3);
+    assert_eq!(glyphs["983044"]["glyph_id"], 4);
+    assert_eq!(glyphs["983046"]["glyph_id"], 6);
+    assert!(glyphs["983044"]["pixel_rect"]["width"].as_u64().unwrap() > 0);
+    assert!(glyphs.get("983042").is_none());
+    assert!(glyphs["983044"].get("mapped_code_point").is_none());
+
+    // The font kerns the f before glyph 4, and glyph 4 before glyph 6.
+    let kerning = metadata["kerning"].as_array().unwrap();
+    let advance = |left: u64, right: u64| kerning.iter()
+        .find(|pair| pair["left"] == left && pair["right"] == right)
+        .and_then(|pair| pair["advance"].as_f64());
+    assert_eq!(advance(102, 983044), Some(-6.4));
+    assert_eq!(advance(983044, 983046), Some(-3.2));
+
+    fs::remove_file("FontGlyphIds.atlas.json")?;
+    fs::remove_file("FontGlyphIds.metrics.json")?;
+    fs::remove_file("FontGlyphIds.bmfa")?;
+
+    Ok(())
+}
+
+/// Glyphs picked by name, either through a glyph name map or through the PostScript glyph
+/// names of the font, should be written to a table keyed by name.
+#[test]
+fn generate_a_font_sheet_with_named_icons() -> Result<(), Box<std::error::Error>> {
+    let mut cmd = Command::cargo_bin("fontgen")?;
+    cmd.arg("--input")
+        .arg("assets/IconSquares.ttf")
+        .arg("--output")
+        .arg("FontIcons.bmfa")
+        .arg("--trim")
+        .arg("--glyph-names")
+        .arg("fa-heart,bolt")
+        .arg("--glyph-name-map")
+        .arg("assets/IconSquares.codepoints");
+    cmd.assert().success();
+
+    // The heart sits at U+F004, and the bolt has no code point.
+    let names: serde_json::Value = serde_json::from_str(&fs::read_to_string("FontIcons.names.json")?)?;
+    assert_eq!(names["fa-heart"]["glyph_id"], 2);
+    assert_eq!(names["fa-heart"]["mapped_code_point"], 0xF004);
+    assert_eq!(names["bolt"]["glyph_id"], 4);
+    assert!(names["bolt"]["width"].as_u64().unwrap() > 0);
+    assert!(names.get("fa-star").is_none());
+
+    fs::remove_file("FontIcons.names.json")?;
+    fs::remove_file("FontIcons.metrics.json")?;
+    fs::remove_file("FontIcons.bmfa")?;
+
+    // A missing name only blames the glyph name map when there is one.
+    fs::write("FontIconsMissing.codepoints", "fa-missing f0ff\n")?;
+    let missing_names: [(&str, Option<&str>, &str); 3] = [
+        ("fa-nothing", None, "NoGlyphNamed(\"fa-nothing\")"),
+        ("fa-nothing", Some("assets/IconSquares.codepoints"), "NoGlyphNamedInMap(\"fa-nothing\""),
+        ("fa-missing", Some("FontIconsMissing.codepoints"), "NoGlyphForMappedName(\"fa-missing\", 61695)"),
+    ];
+    for &(name, glyph_name_map, error) in missing_names.iter() {
+        let mut cmd = Command::cargo_bin("fontgen")?;
+        cmd.arg("--input")
+            .arg("assets/IconSquares.ttf")
+            .arg("--output")
+            .arg("FontIconsMissing.bmfa")
+            .arg("--trim")
+            .arg("--glyph-names")
+            .arg(name);
+        if let Some(glyph_name_map) = glyph_name_map {
+            cmd.arg("--glyph-name-map").arg(glyph_name_map);
+        }
+        cmd.assert()
+            .failure()
+            .stderr(predicate::str::contains(error));
+    }
+    fs::remove_file("FontIconsMissing.codepoints")?;
+
+    Ok(())
+}
+
+/// Watching a font should report every input file it watches, and rebuild the atlas when the
+/// glyph name map changes.
+#[test]
+fn watch_a_font_and_its_glyph_name_map() -> Result<(), Box<std::error::Error>> {
+    fs::copy("assets/IconSquares.codepoints", "FontIconsWatch.codepoints")?;
+    let log = fs::File::create("FontIconsWatch.log")?;
+    let mut child = Command::cargo_bin("fontgen")?
+        .arg("--input")
+        .arg("assets/IconSquares.ttf")
+        .arg("--output")
+        .arg("FontIconsWatch.bmfa")
+        .arg("--trim")
+        .arg("--glyph-names")
+        .arg("fa-heart")
+        .arg("--glyph-name-map")
+        .arg("FontIconsWatch.codepoints")
+        .arg("watch")
+        .arg("--interval")
+        .arg("50")
+        .stdout(std::process::Stdio::from(log))
+        .spawn()?;
+
+    // Wait for the watcher to report a number of builds, giving up after a while.
+    let wait_for_builds = |builds: usize| -> Result<String, Box<std::error::Error>> {
+        for _ in 0..300 {
+            let output = fs::read_to_string("FontIconsWatch.log")?;
+            if output.matches("Generated atlas").count() >= builds && output.contains("Watching") {
+                return Ok(output);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        Ok(fs::read_to_string("FontIconsWatch.log")?)
+    };
+    let first_output = wait_for_builds(1)?;
+    fs::write("FontIconsWatch.codepoints", "# A new comment.\nfa-heart f004\n")?;
+    let second_output = wait_for_builds(2)?;
+    child.kill()?;
+    child.wait()?;
+
+    assert!(first_output.contains("Watching assets/IconSquares.ttf for changes."));
+    assert!(first_output.contains("Watching FontIconsWatch.codepoints for changes."));
+    assert_eq!(second_output.matches("Generated atlas").count(), 2);
+
+    fs::remove_file("FontIconsWatch.codepoints")?;
+    fs::remove_file("FontIconsWatch.log")?;
+    fs::remove_file("FontIconsWatch.names.json")?;
+    fs::remove_file("FontIconsWatch.metrics.json")?;
+    fs::remove_file("FontIconsWatch.bmfa")?;
+
+    Ok(())
+}